@@ -15,13 +15,26 @@ export type QueueState = {
 export type StatsState = {
   speed: number; // Bytes per second
   time: number; // Seconds,
+  threads: number;
+  sources: Array<{ name: string; speed: number; healthy: boolean }>;
+  wireBytes: number;
+  diskBytes: number;
+  compressionRatio: number; // Bytes on disk per byte downloaded
 };
 
 export const useQueueState = () =>
   useState<QueueState>("queue", () => ({ queue: [], status: "Unknown" }));
 
 export const useStatsState = () =>
-  useState<StatsState>("stats", () => ({ speed: 0, time: 0 }));
+  useState<StatsState>("stats", () => ({
+    speed: 0,
+    time: 0,
+    threads: 0,
+    sources: [],
+    wireBytes: 0,
+    diskBytes: 0,
+    compressionRatio: 1,
+  }));
 
 listen("update_queue", (event) => {
   const queue = useQueueState();
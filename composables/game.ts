@@ -1,15 +1,27 @@
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
-import type { Game, GameStatus, GameStatusEnum } from "~/types";
+import { GameStatusEnum, type Game, type GameStatus } from "~/types";
 
 const gameRegistry: { [key: string]: Game } = {};
 
 const gameStatusRegistry: { [key: string]: Ref<GameStatus> } = {};
 
+// Long-running work on an installed game, and how far along it is
+export type GameJob = {
+  status: GameStatusEnum;
+  current: number;
+  max: number;
+};
+
+const gameJobRegistry: { [key: string]: Ref<GameJob | undefined> } = {};
+
+const jobStatuses = [GameStatusEnum.Uninstalling, GameStatusEnum.Moving];
+
 type OptionGameStatus = { [key in GameStatusEnum]: { version_name?: string } };
 export type SerializedGameStatus = [
   { type: GameStatusEnum },
-  OptionGameStatus | null
+  OptionGameStatus | null,
+  { versionName: string } | null
 ];
 
 export const parseStatus = (status: SerializedGameStatus): GameStatus => {
@@ -17,6 +29,7 @@ export const parseStatus = (status: SerializedGameStatus): GameStatus => {
   if (status[0]) {
     return {
       type: status[0].type,
+      updateAvailable: status[2]?.versionName,
     };
   } else if (status[1]) {
     const [[gameStatus, options]] = Object.entries(status[1]);
@@ -47,6 +60,11 @@ export const useGame = async (gameId: string) => {
         } = event.payload as any;
         console.log(payload.status);
         gameStatusRegistry[gameId].value = parseStatus(payload.status);
+        // Finished or failed, either way there's nothing left to show
+        const job = gameJobRegistry[gameId];
+        if (job && !jobStatuses.includes(gameStatusRegistry[gameId].value.type)) {
+          job.value = undefined;
+        }
       });
     }
   }
@@ -54,4 +72,37 @@ export const useGame = async (gameId: string) => {
   const game = gameRegistry[gameId];
   const status = gameStatusRegistry[gameId];
   return { game, status };
-};
\ No newline at end of file
+};
+export const useGameJob = (gameId: string) => {
+  if (!gameJobRegistry[gameId]) {
+    const job = ref<GameJob | undefined>();
+    gameJobRegistry[gameId] = job;
+
+    listen(`update_uninstall/${gameId}`, (event) => {
+      const payload = event.payload as { removed: number; total: number };
+      job.value = {
+        status: GameStatusEnum.Uninstalling,
+        current: payload.removed,
+        max: payload.total,
+      };
+    });
+    listen(`update_snapshot/${gameId}`, (event) => {
+      const payload = event.payload as { current: number; max: number };
+      job.value = {
+        status: GameStatusEnum.Updating,
+        current: payload.current,
+        max: payload.max,
+      };
+    });
+    listen(`update_move/${gameId}`, (event) => {
+      const payload = event.payload as { current: number; max: number };
+      job.value = {
+        status: GameStatusEnum.Moving,
+        current: payload.current,
+        max: payload.max,
+      };
+    });
+  }
+
+  return gameJobRegistry[gameId];
+};
@@ -0,0 +1,11 @@
+import { listen } from "@tauri-apps/api/event";
+import type { Settings } from "~/types";
+
+// Only filled in once settings have changed, pages fetch their own first
+export const useSettings = () =>
+  useState<Settings | undefined>("settings", () => undefined);
+
+listen("settings_changed", (event) => {
+  const settings = useSettings();
+  settings.value = event.payload as Settings;
+});
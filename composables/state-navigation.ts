@@ -33,6 +33,28 @@ export function setupHooks() {
     );
   });
 
+  // Jobs on installed games only report failures through these events
+  const jobErrors: { [event: string]: string } = {
+    uninstall_error: "uninstalling",
+    move_error: "moving",
+    rollback_error: "rolling back",
+  };
+  for (const [jobEvent, action] of Object.entries(jobErrors)) {
+    listen(jobEvent, (event) => {
+      createModal(
+        ModalType.Notification,
+        {
+          title: `Drop encountered an error while ${action} a game`,
+          description: `Drop encountered an error while ${action} your game: "${(
+            event.payload as unknown as string
+          ).toString()}"`,
+          buttonText: "Close"
+        },
+        (e, c) => c()
+      );
+    });
+  }
+
   /*
 
   document.addEventListener("contextmenu", (event) => {
@@ -46,20 +68,25 @@ export function setupHooks() {
 export function initialNavigation(state: Ref<AppState>) {
   const router = useRouter();
 
-  switch (state.value.status) {
+  router.push(statusRoute(state.value.status));
+}
+
+// Where the app should be for a given status
+export function statusRoute(status: AppStatus) {
+  switch (status) {
     case AppStatus.NotConfigured:
-      router.push({ path: "/setup" });
-      break;
+      return "/setup";
     case AppStatus.SignedOut:
-      router.push("/auth");
-      break;
+      return "/auth";
     case AppStatus.SignedInNeedsReauth:
-      router.push("/auth/signedout");
-      break;
+      return "/auth/signedout";
     case AppStatus.ServerUnavailable:
-      router.push("/error/serverunavailable");
-      break;
+      return "/error/serverunavailable";
+    case AppStatus.CredentialsLocked:
+      return "/auth/unlock";
+    case AppStatus.CredentialStoreUnavailable:
+      return "/error/credentials";
     default:
-      router.push("/store");
+      return "/store";
   }
 }
@@ -1,5 +1,7 @@
 import { convertFileSrc } from "@tauri-apps/api/core";
 
-export const useObject = async (id: string) => {
-  return convertFileSrc(id, "object");
+// Passing the game an object belongs to fetches it from that game's server
+export const useObject = async (id: string, gameId?: string) => {
+  const url = convertFileSrc(id, "object");
+  return gameId ? `${url}?game=${encodeURIComponent(gameId)}` : url;
 };
@@ -14,17 +14,18 @@ pub fn toggle_autostart_logic(app: AppHandle, enabled: bool) -> Result<(), Strin
     }
 
     // Store the state in DB
-    let mut db_handle = borrow_db_mut_checked();
+    let mut db_handle = borrow_db_mut_checked().map_err(|e| e.to_string())?;
     db_handle.settings.autostart = enabled;
     drop(db_handle);
-    save_db();
+    save_db().map_err(|e| e.to_string())?;
 
     Ok(())
 }
 
 pub fn get_autostart_enabled_logic(app: AppHandle) -> Result<bool, tauri_plugin_autostart::Error> {
     // First check DB state
-    let db_handle = borrow_db_checked();
+    let db_handle =
+        borrow_db_checked().map_err(|e| tauri_plugin_autostart::Error::Anyhow(e.to_string()))?;
     let db_state = db_handle.settings.autostart;
     drop(db_handle);
 
@@ -46,7 +47,7 @@ pub fn get_autostart_enabled_logic(app: AppHandle) -> Result<bool, tauri_plugin_
 
 // New function to sync state on startup
 pub fn sync_autostart_on_startup(app: &AppHandle) -> Result<(), String> {
-    let db_handle = borrow_db_checked();
+    let db_handle = borrow_db_checked().map_err(|e| e.to_string())?;
     let should_be_enabled = db_handle.settings.autostart;
     drop(db_handle);
 
@@ -0,0 +1,113 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use rustbreak::{backend::Backend, error::BackendResult};
+
+/// Stores the database in a single file, which is replaced as a whole on
+/// every save. A crash part way through leaves the previous copy intact
+#[derive(Debug)]
+pub struct DropDatabaseBackend {
+    path: PathBuf,
+}
+impl DropDatabaseBackend {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+impl Backend for DropDatabaseBackend {
+    fn get_data(&mut self) -> BackendResult<Vec<u8>> {
+        Ok(fs::read(&self.path)?)
+    }
+
+    fn put_data(&mut self, data: &[u8]) -> BackendResult<()> {
+        Ok(write_private_atomically(&self.path, data)?)
+    }
+}
+
+/// Writes to a temporary file next to `path`, syncs it, then renames it over
+/// `path`, so readers only ever see the old or the new contents. The file
+/// can only be read by the current user, as the database, its backups and
+/// the credential store all hold secrets
+pub fn write_private_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // The mode only applies to new files, so a leftover one is removed first
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        if temp_path.exists() {
+            fs::remove_file(&temp_path)?;
+        }
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp_path, path)?;
+
+    // The rename itself only survives a power loss once the directory is synced
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Takes away access for anyone but the current user. On Windows, the data
+/// directory is already private to the user
+pub fn restrict_to_owner(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// drop.db.1 is the newest backup, up to drop.db.<count>
+pub fn backup_path(db_path: &Path, n: usize) -> PathBuf {
+    let mut name = db_path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}", n));
+    db_path.with_file_name(name)
+}
+
+/// Every backup on disk, newest first
+pub fn list_backups(db_path: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|n| backup_path(db_path, n))
+        .take_while(|path| path.exists())
+        .collect()
+}
+
+/// Shifts every backup along by one and copies the database in as the
+/// newest, dropping any beyond `count`
+pub fn rotate_backups(db_path: &Path, count: usize) -> io::Result<()> {
+    let existing = list_backups(db_path);
+    for stale in existing.iter().skip(count.saturating_sub(1)).rev() {
+        fs::remove_file(stale)?;
+    }
+    if count == 0 {
+        return Ok(());
+    }
+
+    for n in (1..=existing.len().min(count - 1)).rev() {
+        let shifted = backup_path(db_path, n + 1);
+        fs::rename(backup_path(db_path, n), &shifted)?;
+        // Backups from before they were written owner-only
+        restrict_to_owner(&shifted)?;
+    }
+    write_private_atomically(&backup_path(db_path, 1), &fs::read(db_path)?)
+}
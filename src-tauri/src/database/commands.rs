@@ -4,32 +4,73 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use log::{info, warn};
 use serde_json::Value;
+use tauri::{AppHandle, Emitter};
 
 use crate::{
+    autostart::sync_autostart_on_startup,
     database::{db::borrow_db_mut_checked, settings::Settings},
-    download_manager::internal_error::InternalError,
+    download_manager::{
+        download_concurrency::set_max_download_threads, internal_error::InternalError,
+    },
+    error::{database_error::DatabaseError, settings_error::SettingsError},
+    remote::client::reset_remote_clients,
 };
 
 use super::{
     db::{borrow_db_checked, save_db, DATA_ROOT_DIR},
     debug::SystemData,
+    dir_stats::{collect_download_dir_stats, DownloadDirStats},
 };
 
-// Will, in future, return disk/remaining size
-// Just returns the directories that have been set up
 #[tauri::command]
-pub fn fetch_download_dir_stats() -> Vec<PathBuf> {
-    let lock = borrow_db_checked();
-    lock.applications.install_dirs.clone()
+pub fn fetch_download_dir_stats() -> Result<Vec<DownloadDirStats>, DatabaseError> {
+    collect_download_dir_stats()
 }
 
 #[tauri::command]
-pub fn delete_download_dir(index: usize) {
-    let mut lock = borrow_db_mut_checked();
-    lock.applications.install_dirs.remove(index);
+pub fn delete_download_dir(index: usize) -> Result<(), DatabaseError> {
+    let mut lock = borrow_db_mut_checked()?;
+    let removed = lock.applications.install_dirs.remove(index);
+    lock.applications.depots.remove(&removed);
     drop(lock);
-    save_db();
+    save_db()
+}
+
+/// Points an install directory at a depot, a local or network mounted
+/// copy of the server's library that chunks are copied from rather than
+/// downloaded. Passing None goes back to downloading from the server
+#[tauri::command]
+pub fn set_download_dir_depot(
+    index: usize,
+    depot: Option<PathBuf>,
+) -> Result<(), InternalError<()>> {
+    if let Some(depot) = &depot {
+        if !depot.is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "Selected depot is not an existing directory",
+            )
+            .into());
+        }
+    }
+
+    let mut lock = borrow_db_mut_checked()?;
+    let install_dir = match lock.applications.install_dirs.get(index) {
+        Some(install_dir) => install_dir.clone(),
+        None => {
+            return Err(Error::new(ErrorKind::NotFound, "Install directory does not exist").into())
+        }
+    };
+    match depot {
+        Some(depot) => lock.applications.depots.insert(install_dir, depot),
+        None => lock.applications.depots.remove(&install_dir),
+    };
+    drop(lock);
+    save_db()?;
+
+    Ok(())
 }
 
 #[tauri::command]
@@ -50,7 +91,7 @@ pub fn add_download_dir(new_dir: PathBuf) -> Result<(), InternalError<()>> {
     }
 
     // Add it to the dictionary
-    let mut lock = borrow_db_mut_checked();
+    let mut lock = borrow_db_mut_checked()?;
     if lock.applications.install_dirs.contains(&new_dir) {
         return Err(Error::new(
             ErrorKind::AlreadyExists,
@@ -60,33 +101,87 @@ pub fn add_download_dir(new_dir: PathBuf) -> Result<(), InternalError<()>> {
     }
     lock.applications.install_dirs.push(new_dir);
     drop(lock);
-    save_db();
+    save_db()?;
 
     Ok(())
 }
 
+/// Merges the given settings into the current ones. Nothing changes unless
+/// every value is valid
 #[tauri::command]
-pub fn update_settings(new_settings: Value) {
-    let mut db_lock = borrow_db_mut_checked();
-    let mut current_settings = serde_json::to_value(db_lock.settings.clone()).unwrap();
-    for (key, value) in new_settings.as_object().unwrap() {
-        current_settings[key] = value.clone();
+pub fn update_settings(new_settings: Value, app_handle: AppHandle) -> Result<(), SettingsError> {
+    let changes = new_settings.as_object().ok_or(SettingsError::NotAnObject)?;
+
+    let mut db_lock = borrow_db_mut_checked()?;
+    let previous = db_lock.settings.clone();
+    let mut merged =
+        serde_json::to_value(&previous).map_err(|e| SettingsError::InvalidValue(e.to_string()))?;
+    for (key, value) in changes {
+        if merged.get(key).is_none() {
+            return Err(SettingsError::UnknownSetting(key.clone()));
+        }
+        merged[key] = value.clone();
+    }
+    let mut settings: Settings =
+        serde_json::from_value(merged).map_err(|e| SettingsError::InvalidValue(e.to_string()))?;
+    settings.validate()?;
+    if settings.proxy != previous.proxy {
+        settings.store_proxy_password()?;
+        // Only the password changed, which apply_settings can't see
+        if settings.proxy == previous.proxy {
+            reset_remote_clients();
+        }
+    }
+
+    db_lock.settings = settings.clone();
+    drop(db_lock);
+    // Values aren't logged, as they can include proxy credentials
+    info!(
+        "updated settings: {}",
+        changes.keys().cloned().collect::<Vec<_>>().join(", ")
+    );
+
+    // Still applied if saving fails, as they're kept in memory until
+    // the next successful save
+    let saved = save_db();
+    apply_settings(&app_handle, &previous, &settings);
+    if let Err(e) = app_handle.emit("settings_changed", &settings) {
+        warn!("could not send updated settings to the frontend: {}", e);
+    }
+
+    Ok(saved?)
+}
+
+/// Pushes changed settings to whatever depends on them
+fn apply_settings(app_handle: &AppHandle, previous: &Settings, settings: &Settings) {
+    set_max_download_threads(settings.max_download_threads);
+
+    if previous.connect_timeout != settings.connect_timeout
+        || previous.read_timeout != settings.read_timeout
+        || previous.server_tls != settings.server_tls
+        || previous.proxy != settings.proxy
+    {
+        reset_remote_clients();
+    }
+
+    if previous.autostart != settings.autostart {
+        if let Err(e) = sync_autostart_on_startup(app_handle) {
+            warn!("failed to apply autostart setting: {}", e);
+        }
     }
-    let new_settings: Settings = serde_json::from_value(current_settings).unwrap();
-    db_lock.settings = new_settings;
-    println!("new Settings: {:?}", db_lock.settings);
 }
 #[tauri::command]
-pub fn fetch_settings() -> Settings {
-    borrow_db_checked().settings.clone()
+pub fn fetch_settings() -> Result<Settings, DatabaseError> {
+    Ok(borrow_db_checked()?.settings.clone())
 }
 #[tauri::command]
-pub fn fetch_system_data() -> SystemData {
-    let db_handle = borrow_db_checked();
-    SystemData::new(
-        db_handle.auth.as_ref().unwrap().client_id.clone(),
-        db_handle.base_url.clone(),
+pub fn fetch_system_data() -> Result<SystemData, DatabaseError> {
+    let db_handle = borrow_db_checked()?;
+    let profile = db_handle.active_profile().cloned().unwrap_or_default();
+    Ok(SystemData::new(
+        profile.auth.map(|auth| auth.client_id).unwrap_or_default(),
+        profile.base_url,
         DATA_ROOT_DIR.lock().unwrap().to_string_lossy().to_string(),
         std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
-    )
+    ))
 }
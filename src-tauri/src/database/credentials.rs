@@ -0,0 +1,473 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use log::{info, warn};
+use openssl::{
+    hash::MessageDigest,
+    pkcs5::pbkdf2_hmac,
+    rand::rand_bytes,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{
+        backend::{restrict_to_owner, write_private_atomically},
+        db::DATA_ROOT_DIR,
+    },
+    error::credential_error::CredentialError,
+};
+
+const CREDENTIALS_FILE: &str = "credentials.json";
+
+const KEYRING_SERVICE: &str = "drop-app";
+const KEYRING_USER: &str = "credential-store";
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: usize = 600_000;
+
+// Encrypted alongside the keys, so a wrong passphrase is caught even when
+// there are no keys stored yet
+const CHECK_VALUE: &[u8] = b"drop";
+
+/// How the private keys in the credential store are protected
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProtectionKind {
+    /// Only by the file permissions
+    None,
+    /// Encrypted with a random key kept in the OS keyring
+    Keyring,
+    /// Encrypted with a key derived from a passphrase, asked for on startup
+    Passphrase,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialStatus {
+    protection: ProtectionKind,
+    locked: bool,
+    keyring_available: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "type")]
+enum Protection {
+    #[default]
+    None {},
+    Keyring {},
+    Passphrase {
+        salt: String,
+    },
+}
+impl Protection {
+    fn kind(&self) -> ProtectionKind {
+        match self {
+            Protection::None {} => ProtectionKind::None,
+            Protection::Keyring {} => ProtectionKind::Keyring,
+            Protection::Passphrase { .. } => ProtectionKind::Passphrase,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CredentialFile {
+    protection: Protection,
+    check: Option<String>,
+    // Profile name to PEM, or to the hex encoded nonce, ciphertext and tag
+    // when the store is encrypted
+    keys: HashMap<String, String>,
+    // Other secrets by name, stored the same way as the keys
+    #[serde(default)]
+    secrets: HashMap<String, String>,
+}
+
+struct CredentialStore {
+    file: CredentialFile,
+    // None while a passphrase protected store is locked, and always when
+    // the store isn't encrypted
+    key: Option<[u8; KEY_LEN]>,
+    // Decrypted PEMs, so the key is only derived and used once per profile
+    decrypted: HashMap<String, String>,
+    decrypted_secrets: HashMap<String, String>,
+}
+impl CredentialStore {
+    fn load() -> Result<Self, CredentialError> {
+        let path = credentials_path();
+        let file = match fs::read(&path) {
+            Ok(data) => {
+                // In case it was copied in from somewhere else
+                restrict_to_owner(&path).map_err(|e| io_error(&path, e))?;
+                serde_json::from_slice(&data)
+                    .map_err(|e| CredentialError::Corrupt(e.to_string()))?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::create(),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+
+        let key = match &file.protection {
+            Protection::Keyring {} => Some(keyring_key()?),
+            Protection::None {} | Protection::Passphrase { .. } => None,
+        };
+        Ok(Self {
+            file,
+            key,
+            decrypted: HashMap::new(),
+            decrypted_secrets: HashMap::new(),
+        })
+    }
+
+    // New stores are encrypted with a keyring key wherever there's a keyring
+    fn create() -> Result<Self, CredentialError> {
+        let mut store = Self {
+            file: CredentialFile::default(),
+            key: None,
+            decrypted: HashMap::new(),
+            decrypted_secrets: HashMap::new(),
+        };
+        match new_keyring_key() {
+            Ok(key) => store.protect(Protection::Keyring {}, Some(key))?,
+            Err(e) => warn!("storing credentials without encryption: {}", e),
+        }
+        Ok(store)
+    }
+
+    fn is_locked(&self) -> bool {
+        self.key.is_none() && !matches!(self.file.protection, Protection::None {})
+    }
+
+    fn private_key(&mut self, profile: &str) -> Result<String, CredentialError> {
+        if let Some(pem) = self.decrypted.get(profile) {
+            return Ok(pem.clone());
+        }
+        let stored = self
+            .file
+            .keys
+            .get(profile)
+            .ok_or_else(|| CredentialError::KeyNotFound(profile.to_string()))?;
+
+        let pem = self.reveal(stored)?;
+        self.decrypted.insert(profile.to_string(), pem.clone());
+        Ok(pem)
+    }
+
+    fn secret(&mut self, name: &str) -> Result<Option<String>, CredentialError> {
+        if let Some(value) = self.decrypted_secrets.get(name) {
+            return Ok(Some(value.clone()));
+        }
+        let Some(stored) = self.file.secrets.get(name) else {
+            return Ok(None);
+        };
+        let value = self.reveal(stored)?;
+        self.decrypted_secrets
+            .insert(name.to_string(), value.clone());
+        Ok(Some(value))
+    }
+
+    fn reveal(&self, stored: &str) -> Result<String, CredentialError> {
+        match (&self.file.protection, &self.key) {
+            (Protection::None {}, _) => Ok(stored.to_string()),
+            (_, Some(key)) => String::from_utf8(decrypt(key, stored)?)
+                .map_err(|e| CredentialError::Corrupt(e.to_string())),
+            (_, None) => Err(CredentialError::Locked),
+        }
+    }
+
+    fn conceal(&self, value: &str) -> Result<String, CredentialError> {
+        if self.is_locked() {
+            return Err(CredentialError::Locked);
+        }
+        match &self.key {
+            Some(key) => encrypt(key, value.as_bytes()),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    fn insert(&mut self, profile: &str, pem: String) -> Result<(), CredentialError> {
+        let stored = self.conceal(&pem)?;
+        self.file.keys.insert(profile.to_string(), stored);
+        self.decrypted.insert(profile.to_string(), pem);
+        self.save()
+    }
+
+    fn insert_secret(&mut self, name: &str, value: String) -> Result<(), CredentialError> {
+        let stored = self.conceal(&value)?;
+        self.file.secrets.insert(name.to_string(), stored);
+        self.decrypted_secrets.insert(name.to_string(), value);
+        self.save()
+    }
+
+    fn remove(&mut self, profile: &str) -> Result<(), CredentialError> {
+        self.decrypted.remove(profile);
+        if self.file.keys.remove(profile).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn remove_secret(&mut self, name: &str) -> Result<(), CredentialError> {
+        self.decrypted_secrets.remove(name);
+        if self.file.secrets.remove(name).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn unlock(&mut self, passphrase: &str) -> Result<(), CredentialError> {
+        let Protection::Passphrase { salt } = &self.file.protection else {
+            return Ok(());
+        };
+        let salt = hex::decode(salt).map_err(|e| CredentialError::Corrupt(e.to_string()))?;
+        let key = passphrase_key(passphrase, &salt)?;
+
+        let check = self.file.check.as_ref().ok_or_else(|| {
+            CredentialError::Corrupt("missing passphrase check value".to_string())
+        })?;
+        match decrypt(&key, check) {
+            Ok(value) if value == CHECK_VALUE => {}
+            _ => return Err(CredentialError::WrongPassphrase),
+        }
+
+        self.key = Some(key);
+        Ok(())
+    }
+
+    // Re-encrypts every stored key under the new protection, then saves
+    fn protect(
+        &mut self,
+        protection: Protection,
+        key: Option<[u8; KEY_LEN]>,
+    ) -> Result<(), CredentialError> {
+        if self.is_locked() {
+            return Err(CredentialError::Locked);
+        }
+        let conceal = |value: String| match &key {
+            Some(key) => encrypt(key, value.as_bytes()),
+            None => Ok(value),
+        };
+        let profiles: Vec<String> = self.file.keys.keys().cloned().collect();
+        let mut keys = HashMap::new();
+        for profile in profiles {
+            let pem = self.private_key(&profile)?;
+            keys.insert(profile, conceal(pem)?);
+        }
+        let names: Vec<String> = self.file.secrets.keys().cloned().collect();
+        let mut secrets = HashMap::new();
+        for name in names {
+            let value = self.secret(&name)?.unwrap_or_default();
+            secrets.insert(name, conceal(value)?);
+        }
+
+        self.file = CredentialFile {
+            check: key
+                .as_ref()
+                .map(|key| encrypt(key, CHECK_VALUE))
+                .transpose()?,
+            protection,
+            keys,
+            secrets,
+        };
+        self.key = key;
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), CredentialError> {
+        let path = credentials_path();
+        let data =
+            serde_json::to_vec(&self.file).map_err(|e| CredentialError::Corrupt(e.to_string()))?;
+        write_private_atomically(&path, &data).map_err(|e| io_error(&path, e))
+    }
+}
+
+// Loaded on first use, as most of it is only needed once signed in
+static CREDENTIALS: LazyLock<Mutex<Option<CredentialStore>>> = LazyLock::new(|| Mutex::new(None));
+
+fn with_store<T>(
+    f: impl FnOnce(&mut CredentialStore) -> Result<T, CredentialError>,
+) -> Result<T, CredentialError> {
+    let mut credentials = CREDENTIALS.lock().unwrap();
+    if credentials.is_none() {
+        *credentials = Some(CredentialStore::load()?);
+    }
+    f(credentials.as_mut().unwrap())
+}
+
+/// The PEM encoded private key the server of `profile` issued this client
+pub fn private_key(profile: &str) -> Result<String, CredentialError> {
+    with_store(|store| store.private_key(profile))
+}
+
+pub fn store_private_key(profile: &str, pem: String) -> Result<(), CredentialError> {
+    with_store(|store| store.insert(profile, pem))
+}
+
+pub fn remove_private_key(profile: &str) -> Result<(), CredentialError> {
+    with_store(|store| store.remove(profile))
+}
+
+/// A secret other than a private key, or None if it was never stored
+pub fn secret(name: &str) -> Result<Option<String>, CredentialError> {
+    with_store(|store| store.secret(name))
+}
+
+pub fn store_secret(name: &str, value: String) -> Result<(), CredentialError> {
+    with_store(|store| store.insert_secret(name, value))
+}
+
+pub fn remove_secret(name: &str) -> Result<(), CredentialError> {
+    with_store(|store| store.remove_secret(name))
+}
+
+/// Whether the store needs a passphrase before any key can be read
+pub fn is_locked() -> Result<bool, CredentialError> {
+    with_store(|store| Ok(store.is_locked()))
+}
+
+pub fn unlock(passphrase: &str) -> Result<(), CredentialError> {
+    with_store(|store| store.unlock(passphrase))?;
+    info!("unlocked credential store");
+    Ok(())
+}
+
+pub fn credential_status() -> Result<CredentialStatus, CredentialError> {
+    with_store(|store| {
+        Ok(CredentialStatus {
+            protection: store.file.protection.kind(),
+            locked: store.is_locked(),
+            keyring_available: keyring_entry().is_ok_and(|entry| {
+                matches!(entry.get_password(), Ok(_) | Err(keyring::Error::NoEntry))
+            }),
+        })
+    })
+}
+
+/// Switches how the stored keys are protected. The store has to be
+/// unlocked first, and `passphrase` is only used for `Passphrase`
+pub fn set_protection(
+    kind: ProtectionKind,
+    passphrase: Option<String>,
+) -> Result<(), CredentialError> {
+    with_store(|store| {
+        let previous = store.file.protection.kind();
+        // A new keyring key would leave nothing able to read the old one's keys
+        if kind == previous && kind != ProtectionKind::Passphrase {
+            return Ok(());
+        }
+        let (protection, key) = match kind {
+            ProtectionKind::None => (Protection::None {}, None),
+            ProtectionKind::Keyring => (Protection::Keyring {}, Some(new_keyring_key()?)),
+            ProtectionKind::Passphrase => {
+                let passphrase = passphrase
+                    .filter(|passphrase| !passphrase.is_empty())
+                    .ok_or(CredentialError::PassphraseRequired)?;
+                let mut salt = [0; SALT_LEN];
+                rand_bytes(&mut salt)?;
+                (
+                    Protection::Passphrase {
+                        salt: hex::encode(salt),
+                    },
+                    Some(passphrase_key(&passphrase, &salt)?),
+                )
+            }
+        };
+        store.protect(protection, key)?;
+
+        // Only once nothing depends on the old keyring key any more
+        if previous == ProtectionKind::Keyring && kind != ProtectionKind::Keyring {
+            if let Err(e) = keyring_entry().and_then(|entry| {
+                entry
+                    .delete_credential()
+                    .map_err(|e| CredentialError::KeyringUnavailable(e.to_string()))
+            }) {
+                warn!("could not remove key from keyring: {}", e);
+            }
+        }
+        info!("credential store protection set to {:?}", kind);
+        Ok(())
+    })
+}
+
+fn credentials_path() -> PathBuf {
+    DATA_ROOT_DIR.lock().unwrap().join(CREDENTIALS_FILE)
+}
+
+fn io_error(path: &Path, error: io::Error) -> CredentialError {
+    CredentialError::Io(path.to_path_buf(), Arc::new(error))
+}
+
+fn keyring_entry() -> Result<keyring::Entry, CredentialError> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+        .map_err(|e| CredentialError::KeyringUnavailable(e.to_string()))
+}
+
+fn keyring_key() -> Result<[u8; KEY_LEN], CredentialError> {
+    let encoded = keyring_entry()?
+        .get_password()
+        .map_err(|e| CredentialError::KeyringUnavailable(e.to_string()))?;
+    hex::decode(encoded)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| CredentialError::Corrupt("invalid key in keyring".to_string()))
+}
+
+fn new_keyring_key() -> Result<[u8; KEY_LEN], CredentialError> {
+    let mut key = [0; KEY_LEN];
+    rand_bytes(&mut key)?;
+    keyring_entry()?
+        .set_password(&hex::encode(key))
+        .map_err(|e| CredentialError::KeyringUnavailable(e.to_string()))?;
+    Ok(key)
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], CredentialError> {
+    let mut key = [0; KEY_LEN];
+    pbkdf2_hmac(
+        passphrase.as_bytes(),
+        salt,
+        PBKDF2_ITERATIONS,
+        MessageDigest::sha256(),
+        &mut key,
+    )?;
+    Ok(key)
+}
+
+fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<String, CredentialError> {
+    let mut nonce = [0; NONCE_LEN];
+    rand_bytes(&mut nonce)?;
+    let mut tag = [0; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        &[],
+        plaintext,
+        &mut tag,
+    )?;
+    Ok(hex::encode(
+        [&nonce[..], &ciphertext[..], &tag[..]].concat(),
+    ))
+}
+
+fn decrypt(key: &[u8; KEY_LEN], encoded: &str) -> Result<Vec<u8>, CredentialError> {
+    let data = hex::decode(encoded).map_err(|e| CredentialError::Corrupt(e.to_string()))?;
+    if data.len() < NONCE_LEN + TAG_LEN {
+        return Err(CredentialError::Corrupt("truncated key".to_string()));
+    }
+    let (nonce, rest) = data.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(nonce),
+        &[],
+        ciphertext,
+        tag,
+    )
+    .map_err(|e| CredentialError::Encryption(e.to_string()))
+}
@@ -3,33 +3,55 @@ use std::{
     fs::{self, create_dir_all},
     hash::Hash,
     path::{Path, PathBuf},
-    sync::{LazyLock, Mutex, RwLockReadGuard, RwLockWriteGuard},
+    sync::{Arc, LazyLock, Mutex, RwLockReadGuard, RwLockWriteGuard},
+    thread::sleep,
+    time::Duration,
 };
 
 use chrono::Utc;
 use directories::BaseDirs;
-use log::{debug, error, info};
-use rustbreak::{DeSerError, DeSerializer, PathDatabase, RustbreakError};
+use log::{debug, error, info, warn};
+use rustbreak::{DeSerError, DeSerializer, RustbreakError};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::serde_as;
 use tauri::AppHandle;
-use url::Url;
 
 use crate::{
-    database::settings::Settings,
+    database::{
+        backend::{
+            list_backups, restrict_to_owner, rotate_backups, write_private_atomically,
+            DropDatabaseBackend,
+        },
+        migrations::{migrate_database_file, CURRENT_SCHEMA_VERSION},
+        settings::Settings,
+    },
     download_manager::downloadable_metadata::DownloadableMetadata,
+    error::database_error::DatabaseError,
     games::{library::push_game_update, state::GameStatusManager},
     process::process_manager::Platform,
     DB,
 };
 
+// The private key is kept in the credential store, under the profile's name
 #[derive(serde::Serialize, Clone, Deserialize)]
 pub struct DatabaseAuth {
-    pub private: String,
     pub cert: String,
     pub client_id: String,
 }
 
+/// Name given to the first server set up, including the one configured
+/// before profiles existed
+pub const DEFAULT_PROFILE_NAME: &str = "Default";
+
+const DATABASE_FILE: &str = "drop.db";
+
+// A Drop server, and the credentials this client was issued by it
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ServerProfile {
+    pub base_url: String,
+    pub auth: Option<DatabaseAuth>,
+}
+
 // Strings are version names for a particular game
 #[derive(Serialize, Clone, Deserialize)]
 #[serde(tag = "type")]
@@ -43,6 +65,74 @@ pub enum GameDownloadStatus {
         version_name: String,
         install_dir: String,
     },
+    // An update that stopped part way through writing over the installed
+    // version. It can't be launched until the update is finished
+    PartiallyInstalled {
+        version_name: String,
+        install_dir: String,
+        // The version being replaced, some of whose files may be left over
+        previous_version_name: String,
+    },
+}
+impl GameDownloadStatus {
+    pub fn version_name(&self) -> Option<&String> {
+        match self {
+            GameDownloadStatus::Installed { version_name, .. }
+            | GameDownloadStatus::SetupRequired { version_name, .. }
+            | GameDownloadStatus::PartiallyInstalled { version_name, .. } => Some(version_name),
+            GameDownloadStatus::Remote {} => None,
+        }
+    }
+
+    pub fn install_dir(&self) -> Option<&String> {
+        match self {
+            GameDownloadStatus::Installed { install_dir, .. }
+            | GameDownloadStatus::SetupRequired { install_dir, .. }
+            | GameDownloadStatus::PartiallyInstalled { install_dir, .. } => Some(install_dir),
+            GameDownloadStatus::Remote {} => None,
+        }
+    }
+}
+
+// A newer version of an installed game that the server has published
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailableUpdate {
+    pub version_name: String,
+}
+
+// How the update checker treats new versions of a game
+#[derive(Serialize, Clone, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum UpdatePolicy {
+    // Queue new versions as soon as they're found
+    AutoUpdate {},
+    // Only flag that an update is available
+    #[default]
+    NotifyOnly {},
+    // Stay on this exact version, even if it's older than the newest one
+    Pinned {
+        version_name: String,
+    },
+}
+
+// A copy of the previously installed version, taken before an update
+#[derive(Serialize, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackSnapshot {
+    pub version_name: String,
+    pub path: PathBuf,
+    // What the game's status was before the update, so it can be restored as-is
+    pub status: GameDownloadStatus,
+    pub game_version: Option<GameVersion>,
+}
+
+// The server a game was fetched from, and what that server calls it
+#[derive(Serialize, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSource {
+    pub profile: String,
+    pub server_id: String,
 }
 
 // Stuff that shouldn't be synced to disk
@@ -51,6 +141,8 @@ pub enum ApplicationTransientStatus {
     Downloading { version_name: String },
     Uninstalling {},
     Updating { version_name: String },
+    Moving {},
+    RollingBack {},
     Running {},
 }
 
@@ -85,6 +177,35 @@ pub struct DatabaseApplications {
     pub game_statuses: HashMap<String, GameDownloadStatus>,
     pub game_versions: HashMap<String, HashMap<String, GameVersion>>,
     pub installed_game_version: HashMap<String, DownloadableMetadata>,
+    // Install dir => depot directory that chunks are copied from instead
+    // of being downloaded
+    #[serde(default)]
+    pub depots: HashMap<PathBuf, PathBuf>,
+    // Size in bytes of each installed game, refreshed after installs
+    #[serde(default)]
+    pub game_sizes: HashMap<String, u64>,
+    // Filled in by the update checker, cleared once the update is installed
+    #[serde(default)]
+    pub available_updates: HashMap<String, AvailableUpdate>,
+    // Games without an entry use the default policy
+    #[serde(default)]
+    pub update_policies: HashMap<String, UpdatePolicy>,
+    // At most one per game, replaced by every update
+    #[serde(default)]
+    pub rollback_snapshots: HashMap<String, RollbackSnapshot>,
+    // Version each game was last rolled back from, which automatic
+    // updates skip until another version is installed
+    #[serde(default)]
+    pub rolled_back_versions: HashMap<String, String>,
+    // Every installed version other than the default one, keyed by game ID
+    // and then version name. The default stays in game_statuses
+    #[serde(default)]
+    pub side_by_side_installs: HashMap<String, HashMap<String, GameDownloadStatus>>,
+    // Every other map here is keyed by the client's ID for a game, which
+    // is the server's ID unless another profile already has a game by that
+    // ID. This is the only place the two are tied together
+    #[serde(default)]
+    pub game_sources: HashMap<String, GameSource>,
 
     #[serde(skip)]
     pub transient_statuses: HashMap<DownloadableMetadata, ApplicationTransientStatus>,
@@ -92,32 +213,115 @@ pub struct DatabaseApplications {
 
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Database {
+    // Files are upgraded to CURRENT_SCHEMA_VERSION before they're loaded
+    #[serde(default)]
+    pub schema_version: u32,
     #[serde(default)]
     pub settings: Settings,
-    pub auth: Option<DatabaseAuth>,
-    pub base_url: String,
+    // Keyed by profile name
+    #[serde(default)]
+    pub profiles: HashMap<String, ServerProfile>,
+    // Empty until a server has been set up
+    #[serde(default)]
+    pub active_profile: String,
     pub applications: DatabaseApplications,
     pub prev_database: Option<PathBuf>,
+    // Why prev_database had to be set aside, shown alongside it
+    #[serde(skip)]
+    pub prev_database_reason: Option<String>,
+    // Private keys from before the credential store, by profile, that it
+    // couldn't take during the migration. Moved over on startup
+    #[serde(default)]
+    pub pending_private_keys: HashMap<String, String>,
 }
 impl Database {
     fn new<T: Into<PathBuf>>(games_base_dir: T, prev_database: Option<PathBuf>) -> Self {
         Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
             applications: DatabaseApplications {
                 install_dirs: vec![games_base_dir.into()],
                 game_statuses: HashMap::new(),
                 game_versions: HashMap::new(),
                 installed_game_version: HashMap::new(),
+                depots: HashMap::new(),
+                game_sizes: HashMap::new(),
+                available_updates: HashMap::new(),
+                update_policies: HashMap::new(),
+                rollback_snapshots: HashMap::new(),
+                rolled_back_versions: HashMap::new(),
+                side_by_side_installs: HashMap::new(),
+                game_sources: HashMap::new(),
                 transient_statuses: HashMap::new(),
             },
             prev_database,
-            base_url: "".to_owned(),
-            auth: None,
+            prev_database_reason: None,
+            pending_private_keys: HashMap::new(),
+            profiles: HashMap::new(),
+            active_profile: "".to_owned(),
             settings: Settings {
                 autostart: false,
                 max_download_threads: 4,
+                adaptive_download_threads: false,
+                keep_previous_version: false,
+                ..Default::default()
             },
         }
     }
+
+    pub fn active_profile(&self) -> Option<&ServerProfile> {
+        self.profiles.get(&self.active_profile)
+    }
+
+    pub fn active_profile_mut(&mut self) -> Option<&mut ServerProfile> {
+        self.profiles.get_mut(&self.active_profile)
+    }
+
+    /// The profile a game belongs to. Games that haven't been fetched yet
+    /// belong to the active profile
+    pub fn game_profile(&self, game_id: &str) -> &str {
+        self.applications
+            .game_sources
+            .get(game_id)
+            .map(|source| source.profile.as_str())
+            .unwrap_or(&self.active_profile)
+    }
+
+    /// What the game's server calls it, for anything sent to that server
+    pub fn server_game_id<'a>(&'a self, game_id: &'a str) -> &'a str {
+        self.applications
+            .game_sources
+            .get(game_id)
+            .map(|source| source.server_id.as_str())
+            .unwrap_or(game_id)
+    }
+
+    /// The client's ID for a game from a profile, assigning one the first
+    /// time the game is seen. Two servers can hand out the same ID, so the
+    /// second one to do so gets a numbered ID of its own
+    pub fn local_game_id(&mut self, profile: &str, server_id: &str) -> String {
+        let sources = &mut self.applications.game_sources;
+        if let Some((game_id, _)) = sources
+            .iter()
+            .find(|(_, source)| source.profile == profile && source.server_id == server_id)
+        {
+            return game_id.clone();
+        }
+
+        let mut game_id = server_id.to_string();
+        let mut suffix = 2;
+        while sources.contains_key(&game_id) {
+            game_id = format!("{}_{}", server_id, suffix);
+            suffix += 1;
+        }
+        sources.insert(
+            game_id.clone(),
+            GameSource {
+                profile: profile.to_string(),
+                server_id: server_id.to_string(),
+            },
+        );
+        game_id
+    }
 }
 pub static DATA_ROOT_DIR: LazyLock<Mutex<PathBuf>> =
     LazyLock::new(|| Mutex::new(BaseDirs::new().unwrap().data_dir().join("drop")));
@@ -136,18 +340,21 @@ impl<T: Serialize + DeserializeOwned> DeSerializer<T> for DropDatabaseSerializer
     }
 }
 
+pub fn database_path() -> PathBuf {
+    DATA_ROOT_DIR.lock().unwrap().join(DATABASE_FILE)
+}
+
 pub type DatabaseInterface =
-    rustbreak::Database<Database, rustbreak::backend::PathBackend, DropDatabaseSerializer>;
+    rustbreak::Database<Database, DropDatabaseBackend, DropDatabaseSerializer>;
 
 pub trait DatabaseImpls {
     fn set_up_database() -> DatabaseInterface;
     fn database_is_set_up(&self) -> bool;
-    fn fetch_base_url(&self) -> Url;
 }
 impl DatabaseImpls for DatabaseInterface {
     fn set_up_database() -> DatabaseInterface {
         let data_root_dir = DATA_ROOT_DIR.lock().unwrap();
-        let db_path = data_root_dir.join("drop.db");
+        let db_path = data_root_dir.join(DATABASE_FILE);
         let games_base_dir = data_root_dir.join("games");
         let logs_root_dir = data_root_dir.join("logs");
 
@@ -159,29 +366,43 @@ impl DatabaseImpls for DatabaseInterface {
         let exists = fs::exists(db_path.clone()).unwrap();
 
         match exists {
-            true => match PathDatabase::load_from_path(db_path.clone()) {
-                Ok(db) => db,
-                Err(e) => handle_invalid_database(e, db_path, games_base_dir),
-            },
+            true => {
+                // The file that failed is kept aside and the user is told
+                // where, rather than starting over silently
+                if let Err(e) = migrate_database_file(&db_path) {
+                    error!("{}", e);
+                    return handle_invalid_database(e.to_string(), db_path, games_base_dir);
+                }
+                match load_database(&db_path) {
+                    Ok(db) => {
+                        // Only files that loaded are backed up, so every
+                        // backup is one that's known to work
+                        let backups = db.borrow_data().unwrap().settings.database_backups;
+                        if let Err(e) = rotate_backups(&db_path, backups) {
+                            warn!("could not back up database: {}", e);
+                        }
+                        db
+                    }
+                    Err(e) => handle_invalid_database(e.to_string(), db_path, games_base_dir),
+                }
+            }
             false => {
                 let default = Database::new(games_base_dir, None);
                 debug!(
                     "Creating database at path {}",
                     db_path.as_os_str().to_str().unwrap()
                 );
-                PathDatabase::create_at_path(db_path, default)
-                    .expect("Database could not be created")
+                create_database(db_path, default)
             }
         }
     }
 
     fn database_is_set_up(&self) -> bool {
-        !self.borrow_data().unwrap().base_url.is_empty()
-    }
-
-    fn fetch_base_url(&self) -> Url {
-        let handle = self.borrow_data().unwrap();
-        Url::parse(&handle.base_url).unwrap()
+        self.borrow_data().is_ok_and(|handle| {
+            handle
+                .active_profile()
+                .is_some_and(|profile| !profile.base_url.is_empty())
+        })
     }
 }
 
@@ -189,22 +410,57 @@ pub fn set_game_status<F: FnOnce(&mut RwLockWriteGuard<'_, Database>, &Downloada
     app_handle: &AppHandle,
     meta: DownloadableMetadata,
     setter: F,
-) {
-    let mut db_handle = borrow_db_mut_checked();
+) -> Result<(), DatabaseError> {
+    let mut db_handle = borrow_db_mut_checked()?;
     setter(&mut db_handle, &meta);
     drop(db_handle);
-    save_db();
+    // The new status is already in memory, so the UI should hear about
+    // it even if it couldn't be written out yet
+    let saved = save_db();
 
-    let status = GameStatusManager::fetch_state(&meta.id);
+    let status = GameStatusManager::fetch_state(&meta.id)?;
 
     push_game_update(app_handle, &meta.id, status);
+    saved
+}
+
+fn load_database(db_path: &Path) -> Result<DatabaseInterface, RustbreakError> {
+    let db = DatabaseInterface::from_parts(
+        Database::default(),
+        DropDatabaseBackend::new(db_path.to_path_buf()),
+        DropDatabaseSerializer,
+    );
+    db.load()?;
+    Ok(db)
 }
-// TODO: Make the error relelvant rather than just assume that it's a Deserialize error
+
+fn create_database(db_path: PathBuf, data: Database) -> DatabaseInterface {
+    let db = DatabaseInterface::from_parts(
+        data,
+        DropDatabaseBackend::new(db_path),
+        DropDatabaseSerializer,
+    );
+    db.save().expect("Database could not be created");
+    db
+}
+
+// Puts a backup in place of the database and loads it as if it were the original
+fn restore_backup(backup: &Path, db_path: &Path) -> Result<DatabaseInterface, DatabaseError> {
+    let data =
+        fs::read(backup).map_err(|e| DatabaseError::Io(backup.to_path_buf(), Arc::new(e)))?;
+    write_private_atomically(db_path, &data)
+        .map_err(|e| DatabaseError::Io(db_path.to_path_buf(), Arc::new(e)))?;
+    migrate_database_file(db_path)?;
+    load_database(db_path)
+        .map_err(|e| DatabaseError::Unreadable(backup.to_path_buf(), e.to_string()))
+}
+
 fn handle_invalid_database(
-    _e: RustbreakError,
+    reason: String,
     db_path: PathBuf,
     games_base_dir: PathBuf,
-) -> rustbreak::Database<Database, rustbreak::backend::PathBackend, DropDatabaseSerializer> {
+) -> DatabaseInterface {
+    warn!("could not load database: {}", reason);
     let new_path = {
         let time = Utc::now().timestamp();
         let mut base = db_path.clone();
@@ -216,41 +472,76 @@ fn handle_invalid_database(
         new_path.to_string_lossy().to_string()
     );
     fs::rename(&db_path, &new_path).unwrap();
+    if let Err(e) = restrict_to_owner(&new_path) {
+        warn!("could not restrict access to {}: {}", new_path.display(), e);
+    }
+
+    for backup in list_backups(&db_path) {
+        match restore_backup(&backup, &db_path) {
+            Ok(db) => {
+                warn!("restored database from {}", backup.display());
+                let mut db_handle = db.borrow_data_mut().unwrap();
+                db_handle.prev_database = Some(new_path);
+                db_handle.prev_database_reason = Some(reason);
+                drop(db_handle);
+                return db;
+            }
+            Err(e) => warn!("skipping backup {}: {}", backup.display(), e),
+        }
+    }
 
-    let db = Database::new(
+    let mut db = Database::new(
         games_base_dir.into_os_string().into_string().unwrap(),
         Some(new_path),
     );
+    db.prev_database_reason = Some(reason);
 
-    PathDatabase::create_at_path(db_path, db).expect("Database could not be created")
+    create_database(db_path, db)
 }
 
-pub fn borrow_db_checked<'a>() -> RwLockReadGuard<'a, Database> {
-    match DB.borrow_data() {
-        Ok(data) => data,
-        Err(e) => {
-            error!("database borrow failed with error {}", e);
-            panic!("database borrow failed with error {}", e);
-        }
-    }
+pub fn borrow_db_checked<'a>() -> Result<RwLockReadGuard<'a, Database>, DatabaseError> {
+    DB.borrow_data().map_err(|e| {
+        error!("database borrow failed with error {}", e);
+        DatabaseError::from(e)
+    })
 }
 
-pub fn borrow_db_mut_checked<'a>() -> RwLockWriteGuard<'a, Database> {
-    match DB.borrow_data_mut() {
-        Ok(data) => data,
-        Err(e) => {
-            error!("database borrow mut failed with error {}", e);
-            panic!("database borrow mut failed with error {}", e);
-        }
-    }
+pub fn borrow_db_mut_checked<'a>() -> Result<RwLockWriteGuard<'a, Database>, DatabaseError> {
+    DB.borrow_data_mut().map_err(|e| {
+        error!("database borrow mut failed with error {}", e);
+        DatabaseError::from(e)
+    })
 }
 
-pub fn save_db() {
-    match DB.save() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("database failed to save with error {}", e);
-            panic!("database failed to save with error {}", e)
+const SAVE_ATTEMPTS: u32 = 3;
+const SAVE_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Writes the database out. A full disk is retried a few times, as space
+/// often frees up by itself. If it still fails, the changes stay in memory
+/// and are written by the next save that succeeds
+pub fn save_db() -> Result<(), DatabaseError> {
+    let mut attempt = 1;
+    loop {
+        let error = match DB.save() {
+            Ok(()) => return Ok(()),
+            Err(e) => DatabaseError::from(e),
+        };
+        if !matches!(error, DatabaseError::StorageFull) {
+            error!("database failed to save with error {}", error);
+            return Err(error);
         }
+        if attempt == SAVE_ATTEMPTS {
+            warn!("database could not be saved as the disk is full, giving up for now");
+            return Err(error);
+        }
+
+        warn!(
+            "disk full while saving database, retrying in {}s ({}/{})",
+            SAVE_RETRY_DELAY.as_secs(),
+            attempt,
+            SAVE_ATTEMPTS
+        );
+        sleep(SAVE_RETRY_DELAY);
+        attempt += 1;
     }
 }
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    fs::read_dir,
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::error::database_error::DatabaseError;
+
+use super::db::{borrow_db_checked, borrow_db_mut_checked, save_db, GameDownloadStatus};
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GameDiskUsage {
+    pub game_id: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadDirStats {
+    pub path: PathBuf,
+    pub total_space: u64,
+    pub free_space: u64,
+    pub used_by_games: u64,
+    pub games: Vec<GameDiskUsage>,
+    pub filesystem: Option<String>,
+    // Where chunks for this directory are copied from, if not the server
+    pub depot: Option<PathBuf>,
+}
+
+/// Builds stats for every install directory. Game sizes come from the
+/// cache in the database, and are only measured here for games that were
+/// installed before sizes were tracked
+pub fn collect_download_dir_stats() -> Result<Vec<DownloadDirStats>, DatabaseError> {
+    let db_handle = borrow_db_checked()?;
+    let install_dirs = db_handle.applications.install_dirs.clone();
+    let installed: Vec<(String, PathBuf)> = db_handle
+        .applications
+        .game_statuses
+        .iter()
+        .filter_map(|(game_id, status)| match status {
+            GameDownloadStatus::Installed { install_dir, .. }
+            | GameDownloadStatus::SetupRequired { install_dir, .. }
+            | GameDownloadStatus::PartiallyInstalled { install_dir, .. } => {
+                Some((game_id.clone(), PathBuf::from(install_dir)))
+            }
+            GameDownloadStatus::Remote {} => None,
+        })
+        .collect();
+    let mut sizes = db_handle.applications.game_sizes.clone();
+    let depots = db_handle.applications.depots.clone();
+    drop(db_handle);
+
+    let mut measured = HashMap::new();
+    for (game_id, install_dir) in installed.iter() {
+        if sizes.contains_key(game_id) {
+            continue;
+        }
+        match dir_size(install_dir) {
+            Ok(size) => {
+                measured.insert(game_id.clone(), size);
+            }
+            Err(e) => warn!("could not measure size of {}: {}", game_id, e),
+        }
+    }
+    if !measured.is_empty() {
+        let mut db_handle = borrow_db_mut_checked()?;
+        db_handle.applications.game_sizes.extend(measured.clone());
+        drop(db_handle);
+        // Only a cache, so they'll just be measured again next time
+        if let Err(e) = save_db() {
+            warn!("could not save game sizes: {}", e);
+        }
+        sizes.extend(measured);
+    }
+
+    Ok(install_dirs
+        .into_iter()
+        .map(|path| {
+            let games: Vec<GameDiskUsage> = installed
+                .iter()
+                .filter(|(_, install_dir)| install_dir.starts_with(&path))
+                .filter_map(|(game_id, _)| {
+                    sizes.get(game_id).map(|size| GameDiskUsage {
+                        game_id: game_id.clone(),
+                        size: *size,
+                    })
+                })
+                .collect();
+            let used_by_games = games.iter().map(|game| game.size).sum();
+            let (total_space, free_space) = disk_space(&path).unwrap_or_else(|e| {
+                warn!("could not read disk space for {}: {}", path.display(), e);
+                (0, 0)
+            });
+
+            DownloadDirStats {
+                filesystem: filesystem_type(&path),
+                depot: depots.get(&path).cloned(),
+                path,
+                total_space,
+                free_space,
+                used_by_games,
+                games,
+            }
+        })
+        .collect())
+}
+
+/// Measures an installed game and stores the result in the size cache
+pub fn refresh_game_size(game_id: &String, install_dir: &Path) {
+    match dir_size(install_dir) {
+        Ok(size) => {
+            debug!("{} takes up {} bytes", game_id, size);
+            let Ok(mut db_handle) = borrow_db_mut_checked() else {
+                return;
+            };
+            db_handle
+                .applications
+                .game_sizes
+                .insert(game_id.clone(), size);
+            drop(db_handle);
+            if let Err(e) = save_db() {
+                warn!("could not save size of {}: {}", game_id, e);
+            }
+        }
+        Err(e) => warn!("could not measure size of {}: {}", game_id, e),
+    }
+}
+
+// Doesn't follow symlinks, so files outside the directory aren't counted
+pub fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Returns the total and available space, in bytes, of the
+/// filesystem the path is on
+#[cfg(unix)]
+fn disk_space(path: &Path) -> io::Result<(u64, u64)> {
+    let stats = rustix::fs::statvfs(path)?;
+    Ok((
+        stats.f_blocks * stats.f_frsize,
+        stats.f_bavail * stats.f_frsize,
+    ))
+}
+
+#[cfg(windows)]
+fn disk_space(path: &Path) -> io::Result<(u64, u64)> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide_path: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut available = 0;
+    let mut total = 0;
+    // Safety: the path is null terminated, and the pointers outlive the call
+    let result = unsafe {
+        GetDiskFreeSpaceExW(
+            wide_path.as_ptr(),
+            &mut available,
+            &mut total,
+            std::ptr::null_mut(),
+        )
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((total, available))
+}
+
+// The most specific (and most recent) mount containing the path wins
+#[cfg(target_os = "linux")]
+fn filesystem_type(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = unescape_mount_path(fields.next()?);
+            let fs_type = fields.next()?;
+            Some((mount_point, fs_type))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.as_os_str().len())
+        .map(|(_, fs_type)| fs_type.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn filesystem_type(_path: &Path) -> Option<String> {
+    None
+}
+
+// /proc/self/mounts escapes whitespace and backslashes as octal
+#[cfg(target_os = "linux")]
+fn unescape_mount_path(path: &str) -> PathBuf {
+    PathBuf::from(
+        path.replace("\\040", " ")
+            .replace("\\011", "\t")
+            .replace("\\012", "\n")
+            .replace("\\134", "\\"),
+    )
+}
@@ -0,0 +1,272 @@
+use std::{
+    fs::{self, read_dir},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{debug, info, warn};
+use serde_json::{Map, Value};
+
+use crate::{
+    database::{
+        backend::{list_backups, restrict_to_owner, write_private_atomically},
+        credentials::store_private_key,
+        db::DEFAULT_PROFILE_NAME,
+    },
+    error::database_error::DatabaseError,
+};
+
+/// Bumped whenever a change to `Database` can't be covered by serde
+/// defaults alone, together with a new entry in MIGRATIONS
+pub const CURRENT_SCHEMA_VERSION: u32 = 4;
+
+// The first schema without private keys in the database
+const PRIVATE_KEYS_MOVED_VERSION: u32 = 3;
+
+/// Upgrades the raw JSON of a database by a single schema version
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// MIGRATIONS[n] upgrades a version n database to version n + 1. Files
+/// from before versioning have no schema_version, and count as version 0
+const MIGRATIONS: [Migration; CURRENT_SCHEMA_VERSION as usize] = [
+    add_schema_version,
+    move_server_into_profile,
+    move_private_keys_to_store,
+    add_game_sources,
+];
+
+/// Upgrades the database file at `db_path` in place, before it's loaded.
+///
+/// The original is copied next to it first, and nothing is written unless
+/// every step succeeds. Files that aren't valid JSON are left for the
+/// loader to deal with.
+pub fn migrate_database_file(db_path: &Path) -> Result<(), DatabaseError> {
+    let raw =
+        fs::read(db_path).map_err(|e| DatabaseError::Io(db_path.to_path_buf(), Arc::new(e)))?;
+    let Ok(Value::Object(mut database)) = serde_json::from_slice(&raw) else {
+        return Ok(());
+    };
+
+    let version = schema_version(&database)?;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(DatabaseError::SchemaTooNew(version));
+    }
+    if version == CURRENT_SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let backup_path = db_path.with_extension(format!("db.schema-v{}", version));
+    fs::copy(db_path, &backup_path)
+        .map_err(|e| DatabaseError::Io(backup_path.clone(), Arc::new(e)))?;
+    // Older schemas keep private keys in the database itself. They're
+    // scrubbed from every backup once the migration succeeds
+    if let Err(e) = restrict_to_owner(&backup_path) {
+        warn!(
+            "could not restrict access to {}: {}",
+            backup_path.display(),
+            e
+        );
+    }
+    info!(
+        "upgrading database from schema version {} to {}, original kept at {}",
+        version,
+        CURRENT_SCHEMA_VERSION,
+        backup_path.display()
+    );
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let from = from as u32;
+        migration(&mut database).map_err(|reason| DatabaseError::MigrationFailed(from, reason))?;
+        database.insert("schema_version".to_string(), Value::from(from + 1));
+        debug!("migrated database to schema version {}", from + 1);
+    }
+
+    let serialized = serde_json::to_vec(&database)
+        .map_err(|e| DatabaseError::MigrationFailed(version, e.to_string()))?;
+    write_private_atomically(db_path, &serialized)
+        .map_err(|e| DatabaseError::Io(db_path.to_path_buf(), Arc::new(e)))?;
+
+    if version < PRIVATE_KEYS_MOVED_VERSION {
+        scrub_backups(db_path);
+    }
+    Ok(())
+}
+
+/// Removes private keys and the proxy password from the rotated and
+/// pre-migration backups of the database. A backup restored without them
+/// just needs signing in again, and the password entering again
+pub fn scrub_backups(db_path: &Path) {
+    for backup in list_backups(db_path)
+        .into_iter()
+        .chain(schema_backups(db_path))
+    {
+        if let Err(e) = scrub_secrets(&backup) {
+            warn!(
+                "could not remove private keys from {}: {}",
+                backup.display(),
+                e
+            );
+        }
+    }
+}
+
+// drop.db.schema-v<n>, one for every schema version a migration started from
+fn schema_backups(db_path: &Path) -> Vec<PathBuf> {
+    let mut prefix = db_path.file_name().unwrap_or_default().to_owned();
+    prefix.push(".schema-v");
+    let prefix = prefix.to_string_lossy().to_string();
+    let Some(Ok(entries)) = db_path.parent().map(read_dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect()
+}
+
+// Covers the top-level auth from before profiles, the auth of each
+// profile, keys still waiting for the credential store and the proxy password
+fn scrub_secrets(path: &Path) -> Result<(), DatabaseError> {
+    let raw = fs::read(path).map_err(|e| DatabaseError::Io(path.to_path_buf(), Arc::new(e)))?;
+    let Ok(Value::Object(mut database)) = serde_json::from_slice(&raw) else {
+        return Ok(());
+    };
+
+    let mut changed = database.remove("pending_private_keys").is_some();
+    if let Some(Value::Object(auth)) = database.get_mut("auth") {
+        changed |= auth.remove("private").is_some();
+    }
+    if let Some(Value::Object(profiles)) = database.get_mut("profiles") {
+        for profile in profiles.values_mut() {
+            if let Some(Value::Object(auth)) = profile.get_mut("auth") {
+                changed |= auth.remove("private").is_some();
+            }
+        }
+    }
+    if let Some(Value::Object(credentials)) = database
+        .get_mut("settings")
+        .and_then(|settings| settings.get_mut("proxy"))
+        .and_then(|proxy| proxy.get_mut("credentials"))
+    {
+        changed |= credentials.remove("password").is_some();
+    }
+    if !changed {
+        return Ok(());
+    }
+
+    let serialized =
+        serde_json::to_vec(&database).map_err(|e| DatabaseError::SaveFailed(e.to_string()))?;
+    write_private_atomically(path, &serialized)
+        .map_err(|e| DatabaseError::Io(path.to_path_buf(), Arc::new(e)))
+}
+
+fn schema_version(database: &Map<String, Value>) -> Result<u32, DatabaseError> {
+    let Some(version) = database.get("schema_version") else {
+        return Ok(0);
+    };
+    version
+        .as_u64()
+        .and_then(|version| u32::try_from(version).ok())
+        .ok_or_else(|| {
+            DatabaseError::MigrationFailed(0, format!("invalid schema version {}", version))
+        })
+}
+
+// Every field added before versioning has a serde default, so
+// there's nothing to change besides the version itself
+fn add_schema_version(_database: &mut Map<String, Value>) -> Result<(), String> {
+    Ok(())
+}
+
+// The single server and its credentials become the first profile
+fn move_server_into_profile(database: &mut Map<String, Value>) -> Result<(), String> {
+    let base_url = database
+        .remove("base_url")
+        .unwrap_or_else(|| Value::from(""));
+    let auth = database.remove("auth").unwrap_or(Value::Null);
+    if !base_url.is_string() {
+        return Err(format!("invalid base_url {}", base_url));
+    }
+
+    let mut profiles = Map::new();
+    let mut active_profile = "";
+    if base_url.as_str().is_some_and(|url| !url.is_empty()) {
+        let mut profile = Map::new();
+        profile.insert("base_url".to_string(), base_url);
+        profile.insert("auth".to_string(), auth);
+        profiles.insert(DEFAULT_PROFILE_NAME.to_string(), Value::Object(profile));
+        active_profile = DEFAULT_PROFILE_NAME;
+
+        // Every game known so far came from that server
+        if let Some(Value::Object(applications)) = database.get_mut("applications") {
+            let game_profiles: Map<String, Value> = applications
+                .get("gameStatuses")
+                .and_then(Value::as_object)
+                .map(|statuses| {
+                    statuses
+                        .keys()
+                        .map(|id| (id.clone(), Value::from(DEFAULT_PROFILE_NAME)))
+                        .collect()
+                })
+                .unwrap_or_default();
+            applications.insert("gameProfiles".to_string(), Value::Object(game_profiles));
+        }
+    }
+    database.insert("profiles".to_string(), Value::Object(profiles));
+    database.insert("active_profile".to_string(), Value::from(active_profile));
+    Ok(())
+}
+
+// Private keys move to the credential store, which is only readable by the
+// current user and usually encrypted. The certificate and client ID stay.
+// Keys the store can't take right now (e.g. its keyring is unreachable)
+// wait in pending_private_keys, so the upgrade itself never fails on them
+fn move_private_keys_to_store(database: &mut Map<String, Value>) -> Result<(), String> {
+    let Some(Value::Object(profiles)) = database.get_mut("profiles") else {
+        return Ok(());
+    };
+    let mut pending = Map::new();
+    for (name, profile) in profiles.iter_mut() {
+        let Some(Value::Object(auth)) = profile.get_mut("auth") else {
+            continue;
+        };
+        match auth.remove("private") {
+            Some(Value::String(private)) => {
+                if let Err(e) = store_private_key(name, private.clone()) {
+                    warn!("could not move private key for {}: {}", name, e);
+                    pending.insert(name.clone(), Value::String(private));
+                }
+            }
+            Some(other) => return Err(format!("invalid private key {}", other)),
+            None => {}
+        }
+    }
+    if !pending.is_empty() {
+        database.insert("pending_private_keys".to_string(), Value::Object(pending));
+    }
+    Ok(())
+}
+
+// The profile each game came from becomes its source, and every game
+// known so far keeps the ID its server gave it
+fn add_game_sources(database: &mut Map<String, Value>) -> Result<(), String> {
+    let Some(Value::Object(applications)) = database.get_mut("applications") else {
+        return Ok(());
+    };
+    let game_profiles = match applications.remove("gameProfiles") {
+        Some(Value::Object(game_profiles)) => game_profiles,
+        Some(other) => return Err(format!("invalid game profiles {}", other)),
+        None => return Ok(()),
+    };
+
+    let mut game_sources = Map::new();
+    for (game_id, profile) in game_profiles {
+        let mut source = Map::new();
+        source.insert("profile".to_string(), profile);
+        source.insert("serverId".to_string(), Value::from(game_id.clone()));
+        game_sources.insert(game_id, Value::Object(source));
+    }
+    applications.insert("gameSources".to_string(), Value::Object(game_sources));
+    Ok(())
+}
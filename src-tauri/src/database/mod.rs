@@ -1,4 +1,8 @@
+pub mod backend;
 pub mod commands;
+pub mod credentials;
 pub mod db;
 pub mod debug;
+pub mod dir_stats;
+pub mod migrations;
 pub mod settings;
@@ -1,10 +1,51 @@
+use std::{collections::HashMap, path::PathBuf};
+
 use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    database::credentials,
+    download_manager::download_concurrency::MAX_DOWNLOAD_THREADS,
+    error::{credential_error::CredentialError, settings_error::SettingsError},
+    remote::client::normalise_fingerprint,
+};
+
+const MAX_TIMEOUT: u64 = 600;
+const MAX_DATABASE_BACKUPS: usize = 20;
+const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+/// Name the proxy password is kept under in the credential store
+pub const PROXY_PASSWORD_SECRET: &str = "proxy-password";
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Settings {
     pub autostart: bool,
     pub max_download_threads: usize,
+    // When enabled, max_download_threads is only the starting point
+    // and the download agent picks the thread count itself
+    #[serde(default)]
+    pub adaptive_download_threads: bool,
+    // Keep a copy of the installed version when updating, so it can be rolled back to
+    #[serde(default)]
+    pub keep_previous_version: bool,
+    // Seconds to wait for a connection to the server to open
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    // Seconds to wait on any single read from the server
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64,
+    // Keyed by server address, e.g. https://drop.example.com
+    #[serde(default)]
+    pub server_tls: HashMap<String, ServerTlsSettings>,
+    #[serde(default)]
+    pub proxy: ProxySettings,
+    // How many copies of the database to keep, taken each time it loads
+    #[serde(default = "default_database_backups")]
+    pub database_backups: usize,
+    // Show the games of every signed in server profile, not just the active one
+    #[serde(default)]
+    pub merged_library: bool,
     // ... other settings ...
 }
 impl Default for Settings {
@@ -12,8 +53,154 @@ impl Default for Settings {
         Self {
             autostart: false,
             max_download_threads: 4,
+            adaptive_download_threads: false,
+            keep_previous_version: false,
+            connect_timeout: default_connect_timeout(),
+            read_timeout: default_read_timeout(),
+            server_tls: HashMap::new(),
+            proxy: ProxySettings::default(),
+            database_backups: default_database_backups(),
+            merged_library: false,
+        }
+    }
+}
+impl Settings {
+    /// Catches values that deserialize fine but can't be used
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        check_range(
+            "maxDownloadThreads",
+            self.max_download_threads as u64,
+            1,
+            MAX_DOWNLOAD_THREADS as u64,
+        )?;
+        check_range("connectTimeout", self.connect_timeout, 1, MAX_TIMEOUT)?;
+        check_range("readTimeout", self.read_timeout, 1, MAX_TIMEOUT)?;
+        check_range(
+            "databaseBackups",
+            self.database_backups as u64,
+            0,
+            MAX_DATABASE_BACKUPS as u64,
+        )?;
+
+        for (server, tls_settings) in &self.server_tls {
+            Url::parse(server)
+                .map_err(|e| SettingsError::InvalidUrl(server.clone(), e.to_string()))?;
+            if let Some(fingerprint) = &tls_settings.pinned_fingerprint {
+                // SHA-256 is 32 bytes
+                if normalise_fingerprint(fingerprint).len() != 64 {
+                    return Err(SettingsError::InvalidFingerprint(server.clone()));
+                }
+            }
+            if let Some(ca_bundle) = &tls_settings.ca_bundle {
+                if !ca_bundle.is_file() {
+                    return Err(SettingsError::MissingFile(ca_bundle.clone()));
+                }
+            }
         }
+
+        if let ProxySettings::Manual {
+            http,
+            https,
+            socks5,
+            ..
+        } = &self.proxy
+        {
+            for proxy in [http, https, socks5].into_iter().flatten() {
+                if proxy.is_empty() {
+                    continue;
+                }
+                let url = Url::parse(proxy)
+                    .map_err(|e| SettingsError::InvalidUrl(proxy.clone(), e.to_string()))?;
+                if !PROXY_SCHEMES.contains(&url.scheme()) {
+                    return Err(SettingsError::InvalidUrl(
+                        proxy.clone(),
+                        format!("unsupported proxy scheme {}", url.scheme()),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves a newly entered proxy password into the credential store, and
+    /// removes the stored one once the proxy no longer needs it
+    pub fn store_proxy_password(&mut self) -> Result<(), CredentialError> {
+        match &mut self.proxy {
+            ProxySettings::Manual {
+                credentials: Some(proxy_credentials),
+                ..
+            } => {
+                if !proxy_credentials.password.is_empty() {
+                    let password = std::mem::take(&mut proxy_credentials.password);
+                    credentials::store_secret(PROXY_PASSWORD_SECRET, password)?;
+                }
+                Ok(())
+            }
+            _ => credentials::remove_secret(PROXY_PASSWORD_SECRET),
+        }
+    }
+}
+
+fn check_range(setting: &'static str, value: u64, min: u64, max: u64) -> Result<(), SettingsError> {
+    if value < min || value > max {
+        return Err(SettingsError::OutOfRange(setting, min, max));
     }
+    Ok(())
+}
+
+// Extra trust for a server that doesn't have a publicly trusted certificate
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerTlsSettings {
+    // PEM file of CA certificates to trust alongside the system ones
+    pub ca_bundle: Option<PathBuf>,
+    // SHA-256 of the server's certificate. When set, that exact certificate
+    // is the only one accepted and ca_bundle is ignored
+    pub pinned_fingerprint: Option<String>,
+}
+
+// How requests to the server, its mirrors and the auth flow reach the network
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all_fields = "camelCase")]
+pub enum ProxySettings {
+    // Follow HTTP_PROXY, HTTPS_PROXY, ALL_PROXY and NO_PROXY
+    #[default]
+    System {},
+    // Always connect directly, even if those variables are set
+    Direct {},
+    Manual {
+        // Proxy URLs, e.g. http://proxy.lan:3128 or socks5://proxy.lan:1080.
+        // The SOCKS5 proxy is used for whatever the others don't cover
+        http: Option<String>,
+        https: Option<String>,
+        socks5: Option<String>,
+        // Comma separated hosts, domains and IP ranges to connect to directly
+        #[serde(default)]
+        no_proxy: String,
+        credentials: Option<ProxyCredentials>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProxyCredentials {
+    pub username: String,
+    // Only set while a new password is on its way to the credential store,
+    // so it's never saved in the database
+    #[serde(default)]
+    pub password: String,
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_read_timeout() -> u64 {
+    30
+}
+
+fn default_database_backups() -> usize {
+    3
 }
 // Ideally use pointers instead of a macro to assign the settings
 // fn deserialize_into<T>(v: serde_json::Value, t: &mut T) -> Result<(), serde_json::Error>
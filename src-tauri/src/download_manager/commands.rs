@@ -1,6 +1,11 @@
 use std::sync::Mutex;
 
-use crate::{download_manager::downloadable_metadata::DownloadableMetadata, AppState};
+use crate::{
+    download_manager::{
+        download_manager::CancelPolicy, downloadable_metadata::DownloadableMetadata,
+    },
+    AppState,
+};
 
 #[tauri::command]
 pub fn pause_downloads(state: tauri::State<'_, Mutex<AppState>>) {
@@ -26,6 +31,14 @@ pub fn move_download_in_queue(
 }
 
 #[tauri::command]
-pub fn cancel_game(state: tauri::State<'_, Mutex<AppState>>, meta: DownloadableMetadata) {
-    state.lock().unwrap().download_manager.cancel(meta)
+pub fn cancel_game(
+    state: tauri::State<'_, Mutex<AppState>>,
+    meta: DownloadableMetadata,
+    policy: Option<CancelPolicy>,
+) {
+    state
+        .lock()
+        .unwrap()
+        .download_manager
+        .cancel(meta, policy.unwrap_or_default())
 }
@@ -0,0 +1,230 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use tokio::sync::Notify;
+
+use super::progress_object::ProgressObject;
+
+/// Upper bound on workers, whether the thread count is fixed or picked automatically
+pub const MAX_DOWNLOAD_THREADS: usize = 32;
+const ADAPTIVE_MIN_THREADS: usize = 1;
+/// How long the tuner waits between throughput samples
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(3);
+/// A new worker is only kept if total throughput grows by at least this
+/// share of what each worker was managing before it was added
+const MIN_WORKER_GAIN: f64 = 0.5;
+/// Number of stable samples before the tuner tries adding another worker
+const PROBE_AFTER_SAMPLES: usize = 5;
+
+/// The max_download_threads setting. Fixed limiters check it every time a
+/// worker asks for a permit, so changes apply to running downloads too
+static CONFIGURED_THREADS: AtomicUsize = AtomicUsize::new(4);
+
+pub fn set_max_download_threads(threads: usize) {
+    CONFIGURED_THREADS.store(threads.clamp(1, MAX_DOWNLOAD_THREADS), Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TunerAction {
+    None,
+    Increased,
+    Decreased,
+}
+
+struct TunerState {
+    last_sample: Instant,
+    last_bytes: usize,
+    last_throughput: usize,
+    last_per_thread: usize,
+    last_action: TunerAction,
+    stable_samples: usize,
+}
+
+/// Limits how many chunks are downloaded at once
+///
+/// Chunks are async tasks, so a permit is just a slot rather than a thread.
+/// In fixed mode this hands out as many permits as max_download_threads
+/// currently allows. In adaptive mode,
+/// every time a worker asks for a permit the tuner checks whether a sample
+/// interval has passed, and if so compares the total throughput reported by
+/// the ProgressObject against the previous sample. Workers are added while
+/// each new one still pulls its weight, and removed when throughput levels
+/// off or errors start showing up.
+#[derive(Clone)]
+pub struct DownloadConcurrency {
+    inner: Arc<DownloadConcurrencyInner>,
+}
+
+struct DownloadConcurrencyInner {
+    adaptive: bool,
+    limit: AtomicUsize,
+    active: Mutex<usize>,
+    available: Notify,
+    errors: AtomicUsize,
+    tuner: Mutex<TunerState>,
+    progress: Arc<ProgressObject>,
+}
+
+pub struct ConcurrencyPermit {
+    inner: Arc<DownloadConcurrencyInner>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        let mut active = self.inner.active.lock().unwrap();
+        *active -= 1;
+        drop(active);
+        self.inner.available.notify_one();
+    }
+}
+
+impl DownloadConcurrency {
+    pub fn new(adaptive: bool, progress: Arc<ProgressObject>) -> Self {
+        let limit = CONFIGURED_THREADS
+            .load(Ordering::Relaxed)
+            .clamp(ADAPTIVE_MIN_THREADS, MAX_DOWNLOAD_THREADS);
+        progress.set_active_threads(limit);
+
+        Self {
+            inner: Arc::new(DownloadConcurrencyInner {
+                adaptive,
+                limit: AtomicUsize::new(limit),
+                active: Mutex::new(0),
+                available: Notify::new(),
+                errors: AtomicUsize::new(0),
+                tuner: Mutex::new(TunerState {
+                    last_sample: Instant::now(),
+                    last_bytes: progress.sum(),
+                    last_throughput: 0,
+                    last_per_thread: 0,
+                    last_action: TunerAction::None,
+                    stable_samples: 0,
+                }),
+                progress,
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.inner.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn record_error(&self) {
+        self.inner.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Waits until a worker slot is free
+    pub async fn acquire(&self) -> ConcurrencyPermit {
+        if self.inner.adaptive {
+            self.evaluate();
+        } else {
+            let configured = CONFIGURED_THREADS.load(Ordering::Relaxed);
+            if configured != self.limit() {
+                debug!(
+                    "download threads changed from {} to {}",
+                    self.limit(),
+                    configured
+                );
+                self.set_limit(configured);
+            }
+        }
+
+        loop {
+            // Created before checking, so a slot freed in between isn't missed
+            let available = self.inner.available.notified();
+            {
+                let mut active = self.inner.active.lock().unwrap();
+                if *active < self.limit() {
+                    *active += 1;
+                    break;
+                }
+            }
+            available.await;
+        }
+
+        ConcurrencyPermit {
+            inner: self.inner.clone(),
+        }
+    }
+
+    fn set_limit(&self, limit: usize) {
+        self.inner.limit.store(limit, Ordering::Relaxed);
+        self.inner.progress.set_active_threads(limit);
+        self.inner.available.notify_waiters();
+    }
+
+    fn evaluate(&self) {
+        let mut tuner = self.inner.tuner.lock().unwrap();
+        let elapsed = tuner.last_sample.elapsed();
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+
+        let current_bytes = self.inner.progress.sum();
+        let bytes = current_bytes.saturating_sub(tuner.last_bytes);
+        // Bytes per millisecond is (roughly) kilobytes per second
+        let throughput = bytes / (elapsed.as_millis() as usize).max(1);
+        let errors = self.inner.errors.swap(0, Ordering::Relaxed);
+        let limit = self.limit();
+        let per_thread = throughput / limit.max(1);
+
+        debug!(
+            "download throughput {}kb/s total, {}kb/s per thread over {} threads ({} errors)",
+            throughput, per_thread, limit, errors
+        );
+
+        // Measured against a worker's share rather than the total, so the
+        // bar for adding one is the same with 2 workers as with 20
+        let improved = throughput as f64
+            > tuner.last_throughput as f64 + tuner.last_per_thread as f64 * MIN_WORKER_GAIN;
+
+        let action = if errors > 0 {
+            TunerAction::Decreased
+        } else {
+            match tuner.last_action {
+                TunerAction::None => TunerAction::Increased,
+                TunerAction::Increased if improved => TunerAction::Increased,
+                // The last worker we added didn't help, so take it away again
+                TunerAction::Increased => TunerAction::Decreased,
+                TunerAction::Decreased if tuner.stable_samples >= PROBE_AFTER_SAMPLES => {
+                    TunerAction::Increased
+                }
+                TunerAction::Decreased => TunerAction::None,
+            }
+        };
+
+        let new_limit = match action {
+            TunerAction::Increased => (limit + 1).min(MAX_DOWNLOAD_THREADS),
+            TunerAction::Decreased => limit.saturating_sub(1).max(ADAPTIVE_MIN_THREADS),
+            TunerAction::None => limit,
+        };
+
+        tuner.stable_samples = if new_limit == limit {
+            tuner.stable_samples + 1
+        } else {
+            0
+        };
+        // Holding steady still counts as having backed off, so we keep
+        // probing upwards every so often
+        tuner.last_action = match action {
+            TunerAction::None => TunerAction::Decreased,
+            action => action,
+        };
+        tuner.last_throughput = throughput;
+        tuner.last_per_thread = per_thread;
+        tuner.last_bytes = current_bytes;
+        tuner.last_sample = Instant::now();
+        drop(tuner);
+
+        if new_limit != limit {
+            debug!("adjusting download threads from {} to {}", limit, new_limit);
+            self.set_limit(new_limit);
+        }
+    }
+}
@@ -2,6 +2,7 @@ use std::{
     any::Any,
     collections::VecDeque,
     fmt::Debug,
+    path::PathBuf,
     sync::{
         mpsc::{SendError, Sender},
         Mutex, MutexGuard,
@@ -10,7 +11,7 @@ use std::{
 };
 
 use log::{debug, info};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::error::application_download_error::ApplicationDownloadError;
 
@@ -35,17 +36,27 @@ pub enum DownloadManagerSignal {
     /// then exit
     Finish,
     /// Stops, removes, and tells a download to cleanup
-    Cancel(DownloadableMetadata),
+    /// according to the given CancelPolicy
+    Cancel(DownloadableMetadata, CancelPolicy),
     /// Removes a given application
     Remove(DownloadableMetadata),
     /// Any error which occurs in the agent
     Error(ApplicationDownloadError),
     /// Pushes UI update
     UpdateUIQueue,
-    UpdateUIStats(usize, usize), //kb/s and seconds
+    UpdateUIStats(usize, usize, usize), //kb/s, seconds and active threads
     /// Uninstall download
     /// Takes download ID
     Uninstall(DownloadableMetadata),
+    /// Moves an installed application to
+    /// another install directory
+    Move(DownloadableMetadata, PathBuf),
+    /// Restores the previous version of an
+    /// application from its rollback snapshot
+    Rollback(DownloadableMetadata),
+    /// Called when an uninstall, move or rollback job has
+    /// finished, whether it succeeded or not
+    JobFinished(DownloadableMetadata),
 }
 
 #[derive(Debug, Clone)]
@@ -66,6 +77,18 @@ impl Serialize for DownloadManagerStatus {
     }
 }
 
+/// What a download should do with the data
+/// it has already written when it is cancelled
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CancelPolicy {
+    /// Leave partial files and progress on disk so the
+    /// download can be resumed later
+    #[default]
+    KeepPartial,
+    /// Remove everything the download has written
+    Delete,
+}
+
 #[derive(Serialize, Clone, Debug)]
 pub enum DownloadStatus {
     Queued,
@@ -134,9 +157,9 @@ impl DownloadManager {
             .send(DownloadManagerSignal::UpdateUIQueue)
             .unwrap();
     }
-    pub fn cancel(&self, meta: DownloadableMetadata) {
+    pub fn cancel(&self, meta: DownloadableMetadata, policy: CancelPolicy) {
         self.command_sender
-            .send(DownloadManagerSignal::Cancel(meta))
+            .send(DownloadManagerSignal::Cancel(meta, policy))
             .unwrap();
     }
     pub fn rearrange(&self, current_index: usize, new_index: usize) {
@@ -167,10 +190,7 @@ impl DownloadManager {
         self.command_sender
             .send(DownloadManagerSignal::UpdateUIQueue)
             .unwrap();
-        self.command_sender
-            .send(DownloadManagerSignal::Go)
-            .unwrap();
-
+        self.command_sender.send(DownloadManagerSignal::Go).unwrap();
     }
     pub fn pause_downloads(&self) {
         self.command_sender
@@ -192,6 +212,16 @@ impl DownloadManager {
             .send(DownloadManagerSignal::Uninstall(meta))
             .unwrap();
     }
+    pub fn move_application(&self, meta: DownloadableMetadata, target_base_dir: PathBuf) {
+        self.command_sender
+            .send(DownloadManagerSignal::Move(meta, target_base_dir))
+            .unwrap();
+    }
+    pub fn rollback_application(&self, meta: DownloadableMetadata) {
+        self.command_sender
+            .send(DownloadManagerSignal::Rollback(meta))
+            .unwrap();
+    }
     pub fn get_sender(&self) -> Sender<DownloadManagerSignal> {
         self.command_sender.clone()
     }
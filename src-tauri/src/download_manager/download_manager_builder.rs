@@ -1,5 +1,6 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
     sync::{
         mpsc::{channel, Receiver, Sender},
         Arc, Mutex,
@@ -11,12 +12,20 @@ use log::{debug, error, info, warn};
 use tauri::{AppHandle, Emitter};
 
 use crate::{
-    error::application_download_error::ApplicationDownloadError,
-    games::library::{QueueUpdateEvent, QueueUpdateEventQueueData, StatsUpdateEvent},
+    error::{application_download_error::ApplicationDownloadError, library_error::LibraryError},
+    games::{
+        library::{
+            uninstall_game_logic, QueueUpdateEvent, QueueUpdateEventQueueData, StatsUpdateEvent,
+        },
+        relocation::move_game_logic,
+        rollback::rollback_game_logic,
+    },
 };
 
 use super::{
-    download_manager::{DownloadManager, DownloadManagerSignal, DownloadManagerStatus},
+    download_manager::{
+        CancelPolicy, DownloadManager, DownloadManagerSignal, DownloadManagerStatus,
+    },
     download_thread_control_flag::{DownloadThreadControl, DownloadThreadControlFlag},
     downloadable::Downloadable,
     downloadable_metadata::DownloadableMetadata,
@@ -76,6 +85,12 @@ pub struct DownloadManagerBuilder {
     current_download_agent: Option<DownloadAgent>, // Should be the only download agent in the map with the "Go" flag
     current_download_thread: Mutex<Option<JoinHandle<()>>>,
     active_control_flag: Option<DownloadThreadControl>,
+
+    // Uninstall and move jobs, keyed by game ID, as only
+    // one thing may touch a game's files at once
+    game_jobs: HashMap<String, JoinHandle<()>>,
+    // Uninstalls waiting on a download of the same game to leave the queue
+    pending_uninstalls: VecDeque<DownloadableMetadata>,
 }
 impl DownloadManagerBuilder {
     pub fn build(app_handle: AppHandle) -> DownloadManager {
@@ -96,6 +111,9 @@ impl DownloadManagerBuilder {
             current_download_agent: None,
             current_download_thread: Mutex::new(None),
             active_control_flag: None,
+
+            game_jobs: HashMap::new(),
+            pending_uninstalls: VecDeque::new(),
         };
 
         let terminator = spawn(|| manager.manage_queue());
@@ -164,15 +182,30 @@ impl DownloadManagerBuilder {
                 DownloadManagerSignal::UpdateUIQueue => {
                     self.push_ui_queue_update();
                 }
-                DownloadManagerSignal::UpdateUIStats(kbs, time) => {
-                    self.push_ui_stats_update(kbs, time);
+                DownloadManagerSignal::UpdateUIStats(kbs, time, threads) => {
+                    self.push_ui_stats_update(kbs, time, threads);
                 }
                 DownloadManagerSignal::Finish => {
                     self.stop_and_wait_current_download();
+                    for (_, job_thread) in self.game_jobs.drain() {
+                        job_thread.join().unwrap();
+                    }
                     return Ok(());
                 }
-                DownloadManagerSignal::Cancel(meta) => {
-                    self.manage_cancel_signal(&meta);
+                DownloadManagerSignal::Cancel(meta, policy) => {
+                    self.manage_cancel_signal(&meta, policy);
+                }
+                DownloadManagerSignal::Uninstall(meta) => {
+                    self.manage_uninstall_signal(meta);
+                }
+                DownloadManagerSignal::Move(meta, target_base_dir) => {
+                    self.manage_move_signal(meta, target_base_dir);
+                }
+                DownloadManagerSignal::Rollback(meta) => {
+                    self.manage_rollback_signal(meta);
+                }
+                DownloadManagerSignal::JobFinished(meta) => {
+                    self.manage_job_finished_signal(meta);
                 }
                 _ => {}
             };
@@ -209,7 +242,9 @@ impl DownloadManagerBuilder {
         }
 
         if self.current_download_agent.is_some() {
-            if self.download_queue.read().front().unwrap() == &self.current_download_agent.as_ref().unwrap().metadata() {
+            if self.download_queue.read().front().unwrap()
+                == &self.current_download_agent.as_ref().unwrap().metadata()
+            {
                 debug!(
                     "Current download agent: {:?}",
                     self.current_download_agent.as_ref().unwrap().metadata()
@@ -223,6 +258,14 @@ impl DownloadManagerBuilder {
         // Should always be Some if the above two statements keep going
         let agent_data = self.download_queue.read().front().unwrap().clone();
 
+        if self.game_jobs.contains_key(&agent_data.id) {
+            debug!(
+                "waiting for job on {} to finish before downloading",
+                agent_data.id
+            );
+            return;
+        }
+
         info!("starting download for {:?}", agent_data);
 
         let download_agent = self
@@ -281,6 +324,7 @@ impl DownloadManagerBuilder {
                 self.remove_and_cleanup_front_download(&meta);
             }
         }
+        self.start_ready_uninstalls();
         self.push_ui_queue_update();
         self.sender.send(DownloadManagerSignal::Go).unwrap();
     }
@@ -293,17 +337,21 @@ impl DownloadManagerBuilder {
             self.remove_and_cleanup_front_download(&current_agent.metadata());
         }
         self.set_status(DownloadManagerStatus::Error(error));
+        self.start_ready_uninstalls();
     }
-    fn manage_cancel_signal(&mut self, meta: &DownloadableMetadata) {
-        debug!("got signal Cancel");
+    fn manage_cancel_signal(&mut self, meta: &DownloadableMetadata, policy: CancelPolicy) {
+        debug!("got signal Cancel with policy {:?}", policy);
 
-        if let Some(current_download) = &self.current_download_agent {
+        if let Some(current_download) = self.current_download_agent.clone() {
             if &current_download.metadata() == meta {
                 self.set_status(DownloadManagerStatus::Paused);
-                current_download.on_cancelled(&self.app_handle);
+                // The download thread has to be gone before the agent
+                // is allowed to touch its files
                 self.stop_and_wait_current_download();
+                current_download.on_cancelled(&self.app_handle, policy);
 
                 self.download_queue.pop_front();
+                self.download_agent_registry.remove(meta);
 
                 self.cleanup_current_download();
                 debug!("current download queue: {:?}", self.download_queue.read());
@@ -312,7 +360,7 @@ impl DownloadManagerBuilder {
             else if let Some(download_agent) = self.download_agent_registry.get(meta) {
                 let index = self.download_queue.get_by_meta(meta);
                 if let Some(index) = index {
-                    download_agent.on_cancelled(&self.app_handle);
+                    download_agent.on_cancelled(&self.app_handle, policy);
                     let _ = self.download_queue.edit().remove(index).unwrap();
                     let removed = self.download_agent_registry.remove(meta);
                     debug!(
@@ -325,7 +373,7 @@ impl DownloadManagerBuilder {
         } else if let Some(download_agent) = self.download_agent_registry.get(meta) {
             let index = self.download_queue.get_by_meta(meta);
             if let Some(index) = index {
-                download_agent.on_cancelled(&self.app_handle);
+                download_agent.on_cancelled(&self.app_handle, policy);
                 let _ = self.download_queue.edit().remove(index).unwrap();
                 let removed = self.download_agent_registry.remove(meta);
                 debug!(
@@ -335,10 +383,134 @@ impl DownloadManagerBuilder {
                 );
             }
         }
+        self.start_ready_uninstalls();
         self.push_ui_queue_update();
     }
-    fn push_ui_stats_update(&self, kbs: usize, time: usize) {
-        let event_data = StatsUpdateEvent { speed: kbs, time };
+    fn manage_uninstall_signal(&mut self, meta: DownloadableMetadata) {
+        debug!("got signal Uninstall");
+
+        if self.pending_uninstalls.contains(&meta) {
+            warn!("uninstall for {:?} is already pending", meta);
+            return;
+        }
+
+        if self.is_game_busy(&meta.id) {
+            debug!(
+                "deferring uninstall of {} until it is no longer busy",
+                meta.id
+            );
+            self.pending_uninstalls.push_back(meta);
+            return;
+        }
+
+        self.start_uninstall(meta);
+    }
+    fn manage_move_signal(&mut self, meta: DownloadableMetadata, target_base_dir: PathBuf) {
+        debug!("got signal Move");
+        self.start_exclusive_job(meta, "move_error", move |meta, app_handle| {
+            move_game_logic(meta, target_base_dir, app_handle)
+        });
+    }
+    fn manage_rollback_signal(&mut self, meta: DownloadableMetadata) {
+        debug!("got signal Rollback");
+        self.start_exclusive_job(meta, "rollback_error", rollback_game_logic);
+    }
+    fn manage_job_finished_signal(&mut self, meta: DownloadableMetadata) {
+        debug!("got signal JobFinished");
+        if let Some(job_thread) = self.game_jobs.remove(&meta.id) {
+            job_thread.join().unwrap();
+        }
+        // Anything that was queued for this game while we were busy can go now
+        self.start_ready_uninstalls();
+        self.sender.send(DownloadManagerSignal::Go).unwrap();
+    }
+    /// Whether the game has a job running or a download in the queue
+    fn is_game_busy(&self, game_id: &String) -> bool {
+        self.game_jobs.contains_key(game_id)
+            || self
+                .download_queue
+                .read()
+                .iter()
+                .any(|meta| &meta.id == game_id)
+    }
+    fn start_ready_uninstalls(&mut self) {
+        let pending_uninstalls = std::mem::take(&mut self.pending_uninstalls);
+        let (ready, waiting): (VecDeque<_>, VecDeque<_>) = pending_uninstalls
+            .into_iter()
+            .partition(|meta| !self.is_game_busy(&meta.id));
+        self.pending_uninstalls = waiting;
+        for meta in ready {
+            self.start_uninstall(meta);
+        }
+    }
+    fn start_uninstall(&mut self, meta: DownloadableMetadata) {
+        self.spawn_game_job(meta, "uninstall_error", uninstall_game_logic);
+    }
+    /// Runs a job straight away, or emits a GameBusy error
+    /// if anything else is touching the game
+    fn start_exclusive_job<F>(
+        &mut self,
+        meta: DownloadableMetadata,
+        error_event: &'static str,
+        job: F,
+    ) where
+        F: FnOnce(&DownloadableMetadata, &AppHandle) -> Result<(), LibraryError> + Send + 'static,
+    {
+        if self.is_game_busy(&meta.id) {
+            warn!("refusing to start job on {} while it is busy", meta.id);
+            self.app_handle
+                .emit(
+                    error_event,
+                    LibraryError::GameBusy(meta.id.clone()).to_string(),
+                )
+                .unwrap();
+            return;
+        }
+        self.spawn_game_job(meta, error_event, job);
+    }
+    fn spawn_game_job<F>(&mut self, meta: DownloadableMetadata, error_event: &'static str, job: F)
+    where
+        F: FnOnce(&DownloadableMetadata, &AppHandle) -> Result<(), LibraryError> + Send + 'static,
+    {
+        info!("starting job on {:?}", meta);
+        let sender = self.sender.clone();
+        let app_handle = self.app_handle.clone();
+        let id = meta.id.clone();
+        let job_thread = spawn(move || {
+            if let Err(e) = job(&meta, &app_handle) {
+                error!("job on {:?} failed: {}", meta, e);
+                app_handle.emit(error_event, e.to_string()).unwrap();
+            }
+            sender
+                .send(DownloadManagerSignal::JobFinished(meta))
+                .unwrap();
+        });
+        self.game_jobs.insert(id, job_thread);
+    }
+    fn push_ui_stats_update(&self, kbs: usize, time: usize, threads: usize) {
+        let event_data = match &self.current_download_agent {
+            Some(agent) => {
+                let progress = agent.progress();
+                StatsUpdateEvent {
+                    speed: kbs,
+                    time,
+                    threads,
+                    sources: progress.get_source_stats(),
+                    wire_bytes: progress.get_wire_bytes(),
+                    disk_bytes: progress.get_decoded_bytes(),
+                    compression_ratio: progress.get_compression_ratio(),
+                }
+            }
+            None => StatsUpdateEvent {
+                speed: kbs,
+                time,
+                threads,
+                sources: Vec::new(),
+                wire_bytes: 0,
+                disk_bytes: 0,
+                compression_ratio: 1.0,
+            },
+        };
 
         self.app_handle.emit("update_stats", event_data).unwrap();
     }
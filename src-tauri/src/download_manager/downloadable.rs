@@ -5,8 +5,10 @@ use tauri::AppHandle;
 use crate::error::application_download_error::ApplicationDownloadError;
 
 use super::{
-    download_manager::DownloadStatus, download_thread_control_flag::DownloadThreadControl,
-    downloadable_metadata::DownloadableMetadata, progress_object::ProgressObject,
+    download_manager::{CancelPolicy, DownloadStatus},
+    download_thread_control_flag::DownloadThreadControl,
+    downloadable_metadata::DownloadableMetadata,
+    progress_object::ProgressObject,
 };
 
 pub trait Downloadable: Send + Sync {
@@ -19,5 +21,5 @@ pub trait Downloadable: Send + Sync {
     fn on_error(&self, app_handle: &AppHandle, error: ApplicationDownloadError);
     fn on_complete(&self, app_handle: &AppHandle);
     fn on_incomplete(&self, app_handle: &AppHandle);
-    fn on_cancelled(&self, app_handle: &AppHandle);
+    fn on_cancelled(&self, app_handle: &AppHandle, policy: CancelPolicy);
 }
@@ -2,16 +2,20 @@ use std::{fmt::Display, io, sync::mpsc::SendError};
 
 use serde_with::SerializeDisplay;
 
+use crate::error::database_error::DatabaseError;
+
 #[derive(SerializeDisplay)]
 pub enum InternalError<T> {
     IOError(io::Error),
     SignalError(SendError<T>),
+    DatabaseError(DatabaseError),
 }
 impl<T> Display for InternalError<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             InternalError::IOError(error) => write!(f, "{}", error),
             InternalError::SignalError(send_error) => write!(f, "{}", send_error),
+            InternalError::DatabaseError(error) => write!(f, "{}", error),
         }
     }
 }
@@ -25,3 +29,8 @@ impl<T> From<io::Error> for InternalError<T> {
         InternalError::IOError(value)
     }
 }
+impl<T> From<DatabaseError> for InternalError<T> {
+    fn from(value: DatabaseError) -> Self {
+        InternalError::DatabaseError(value)
+    }
+}
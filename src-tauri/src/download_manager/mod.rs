@@ -1,4 +1,5 @@
 pub mod commands;
+pub mod download_concurrency;
 pub mod download_manager;
 pub mod download_manager_builder;
 pub mod download_thread_control_flag;
@@ -8,6 +8,7 @@ use std::{
 };
 
 use atomic_instant_full::AtomicInstant;
+use serde::Serialize;
 use throttle_my_fn::throttle;
 
 use super::{
@@ -24,6 +25,22 @@ pub struct ProgressObject {
     last_update_time: Arc<AtomicInstant>,
     bytes_last_update: Arc<AtomicUsize>,
     rolling: RollingProgressWindow<250>,
+    active_threads: Arc<AtomicUsize>,
+    source_stats: Arc<Mutex<Vec<DownloadSourceStats>>>,
+    // Bytes received from servers and what they decompressed to. Depot
+    // copies aren't counted, as they're never compressed
+    wire_bytes: Arc<AtomicUsize>,
+    decoded_bytes: Arc<AtomicUsize>,
+}
+
+/// Throughput of a single place a download is pulling data from,
+/// such as a mirror
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadSourceStats {
+    pub name: String,
+    pub speed: usize, // kb/s
+    pub healthy: bool,
 }
 
 pub struct ProgressHandle {
@@ -55,6 +72,24 @@ impl ProgressHandle {
             .fetch_add(amount, Ordering::Relaxed);
         // Dont' fire update
     }
+    /// Counts bytes fetched from a server, before and after decompression
+    pub fn add_transfer(&self, wire: usize, decoded: usize) {
+        self.progress_object
+            .wire_bytes
+            .fetch_add(wire, Ordering::Relaxed);
+        self.progress_object
+            .decoded_bytes
+            .fetch_add(decoded, Ordering::Relaxed);
+    }
+    /// Throws away whatever progress has been made, e.g. to retry a chunk
+    pub fn reset(&self) {
+        let amount = self.progress.swap(0, Ordering::Relaxed);
+        let _ = self.progress_object.bytes_last_update.fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |bytes| Some(bytes.saturating_sub(amount)),
+        );
+    }
 }
 
 impl ProgressObject {
@@ -70,6 +105,10 @@ impl ProgressObject {
             last_update_time: Arc::new(AtomicInstant::now()),
             bytes_last_update: Arc::new(AtomicUsize::new(0)),
             rolling: RollingProgressWindow::new(),
+            active_threads: Arc::new(AtomicUsize::new(0)),
+            source_stats: Arc::new(Mutex::new(Vec::new())),
+            wire_bytes: Arc::new(AtomicUsize::new(0)),
+            decoded_bytes: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -100,6 +139,32 @@ impl ProgressObject {
     pub fn get(&self, index: usize) -> Arc<AtomicUsize> {
         self.progress_instances.lock().unwrap()[index].clone()
     }
+    pub fn set_active_threads(&self, threads: usize) {
+        self.active_threads.store(threads, Ordering::Relaxed);
+    }
+    pub fn get_active_threads(&self) -> usize {
+        self.active_threads.load(Ordering::Relaxed)
+    }
+    pub fn set_source_stats(&self, stats: Vec<DownloadSourceStats>) {
+        *self.source_stats.lock().unwrap() = stats;
+    }
+    pub fn get_source_stats(&self) -> Vec<DownloadSourceStats> {
+        self.source_stats.lock().unwrap().clone()
+    }
+    pub fn get_wire_bytes(&self) -> usize {
+        self.wire_bytes.load(Ordering::Relaxed)
+    }
+    pub fn get_decoded_bytes(&self) -> usize {
+        self.decoded_bytes.load(Ordering::Relaxed)
+    }
+    /// How many bytes ended up on disk for each one sent over the network
+    pub fn get_compression_ratio(&self) -> f64 {
+        let wire_bytes = self.get_wire_bytes();
+        if wire_bytes == 0 {
+            return 1.0;
+        }
+        self.get_decoded_bytes() as f64 / wire_bytes as f64
+    }
     fn update_window(&self, kilobytes_per_second: usize) {
         self.rolling.update(kilobytes_per_second);
     }
@@ -118,11 +183,11 @@ pub fn calculate_update(progress: &ProgressObject) {
         .bytes_last_update
         .swap(current_bytes_downloaded, Ordering::Relaxed);
 
-    let bytes_since_last_update = current_bytes_downloaded - bytes_at_last_update;
+    let bytes_since_last_update = current_bytes_downloaded.saturating_sub(bytes_at_last_update);
 
     let kilobytes_per_second = bytes_since_last_update / (time_since_last_update as usize).max(1);
 
-    let bytes_remaining = max - current_bytes_downloaded; // bytes
+    let bytes_remaining = max.saturating_sub(current_bytes_downloaded); // bytes
 
     progress.update_window(kilobytes_per_second);
     push_update(progress, bytes_remaining);
@@ -143,6 +208,7 @@ fn update_ui(progress_object: &ProgressObject, kilobytes_per_second: usize, time
         .send(DownloadManagerSignal::UpdateUIStats(
             kilobytes_per_second,
             time_remaining,
+            progress_object.get_active_threads(),
         ))
         .unwrap();
 }
@@ -5,7 +5,9 @@ use std::{
 
 use serde_with::SerializeDisplay;
 
-use super::{remote_access_error::RemoteAccessError, setup_error::SetupError};
+use super::{
+    database_error::DatabaseError, remote_access_error::RemoteAccessError, setup_error::SetupError,
+};
 
 // TODO: Rename / separate from downloads
 #[derive(Debug, Clone, SerializeDisplay)]
@@ -16,6 +18,7 @@ pub enum ApplicationDownloadError {
     Lock,
     IoError(io::ErrorKind),
     DownloadError,
+    Database(DatabaseError),
 }
 
 impl Display for ApplicationDownloadError {
@@ -27,6 +30,13 @@ impl Display for ApplicationDownloadError {
             ApplicationDownloadError::Checksum => write!(f, "checksum failed to validate for download"),
             ApplicationDownloadError::IoError(error) => write!(f, "{}", error),
             ApplicationDownloadError::DownloadError => write!(f, "download failed. See Download Manager status for specific error"),
+            ApplicationDownloadError::Database(error) => write!(f, "{}", error),
         }
     }
 }
+
+impl From<DatabaseError> for ApplicationDownloadError {
+    fn from(value: DatabaseError) -> Self {
+        ApplicationDownloadError::Database(value)
+    }
+}
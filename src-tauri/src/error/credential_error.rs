@@ -0,0 +1,54 @@
+use std::{fmt::Display, io, path::PathBuf, sync::Arc};
+
+use openssl::error::ErrorStack;
+use serde_with::SerializeDisplay;
+
+#[derive(SerializeDisplay, Debug, Clone)]
+pub enum CredentialError {
+    Io(PathBuf, Arc<io::Error>),
+    Corrupt(String),
+    Locked,
+    WrongPassphrase,
+    PassphraseRequired,
+    KeyringUnavailable(String),
+    KeyNotFound(String),
+    Encryption(String),
+}
+impl Display for CredentialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CredentialError::Io(path, error) => write!(
+                f,
+                "Failed to access the credential store at {}: {}",
+                path.display(),
+                error
+            ),
+            CredentialError::Corrupt(reason) => {
+                write!(f, "The credential store is damaged: {}", reason)
+            }
+            CredentialError::Locked => write!(
+                f,
+                "Your credentials are locked. Enter your passphrase to unlock them"
+            ),
+            CredentialError::WrongPassphrase => write!(f, "The passphrase is incorrect"),
+            CredentialError::PassphraseRequired => write!(f, "A passphrase cannot be empty"),
+            CredentialError::KeyringUnavailable(reason) => {
+                write!(f, "The system keyring is unavailable: {}", reason)
+            }
+            CredentialError::KeyNotFound(profile) => write!(
+                f,
+                "There is no private key stored for server profile {}. Please sign in again",
+                profile
+            ),
+            CredentialError::Encryption(reason) => {
+                write!(f, "Failed to encrypt or decrypt credentials: {}", reason)
+            }
+        }
+    }
+}
+
+impl From<ErrorStack> for CredentialError {
+    fn from(value: ErrorStack) -> Self {
+        CredentialError::Encryption(value.to_string())
+    }
+}
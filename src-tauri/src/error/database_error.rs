@@ -0,0 +1,62 @@
+use std::{fmt::Display, io, path::PathBuf, sync::Arc};
+
+use rustbreak::{error::BackendError, RustbreakError};
+use serde_with::SerializeDisplay;
+
+#[derive(SerializeDisplay, Debug, Clone)]
+pub enum DatabaseError {
+    Io(PathBuf, Arc<io::Error>),
+    Unreadable(PathBuf, String),
+    SchemaTooNew(u32),
+    MigrationFailed(u32, String),
+    Poisoned,
+    StorageFull,
+    SaveFailed(String),
+}
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::Io(path, error) => {
+                write!(f, "Failed to access the database at {}: {}", path.display(), error)
+            }
+            DatabaseError::Unreadable(path, reason) => {
+                write!(f, "Failed to read the database at {}: {}", path.display(), reason)
+            }
+            DatabaseError::SchemaTooNew(version) => write!(
+                f,
+                "The database was written by a newer version of Drop (schema version {}). Please update the client",
+                version
+            ),
+            DatabaseError::MigrationFailed(version, reason) => write!(
+                f,
+                "Failed to upgrade the database from schema version {}: {}",
+                version, reason
+            ),
+            DatabaseError::Poisoned => write!(
+                f,
+                "The database is unavailable after an internal error. Please restart Drop"
+            ),
+            DatabaseError::StorageFull => write!(
+                f,
+                "The disk is full, so changes could not be saved. They will be saved once space is freed up"
+            ),
+            DatabaseError::SaveFailed(reason) => {
+                write!(f, "Failed to save the database: {}", reason)
+            }
+        }
+    }
+}
+impl std::error::Error for DatabaseError {}
+
+impl From<RustbreakError> for DatabaseError {
+    fn from(error: RustbreakError) -> Self {
+        match error {
+            RustbreakError::Poison => DatabaseError::Poisoned,
+            RustbreakError::Backend(BackendError::Io(e)) => match e.kind() {
+                io::ErrorKind::StorageFull => DatabaseError::StorageFull,
+                _ => DatabaseError::SaveFailed(e.to_string()),
+            },
+            error => DatabaseError::SaveFailed(error.to_string()),
+        }
+    }
+}
@@ -1,10 +1,30 @@
-use std::fmt::Display;
+use std::{fmt::Display, io, path::PathBuf};
 
 use serde_with::SerializeDisplay;
 
+use super::{database_error::DatabaseError, remote_access_error::RemoteAccessError};
+
 #[derive(SerializeDisplay)]
 pub enum LibraryError {
     MetaNotFound(String),
+    GameRunning(String),
+    GameBusy(String),
+    UninstallFailed(String, io::Error),
+    MoveFailed(String, io::Error),
+    InvalidInstallDir(usize),
+    OrphanNotFound(PathBuf),
+    OrphanNotAdoptable(PathBuf),
+    NoUpdateAvailable(String),
+    VersionPinned(String, String),
+    NoRollbackAvailable(String),
+    RollbackFailed(String, io::Error),
+    InstallDirNotConfigured(PathBuf),
+    CleanupFailed(PathBuf, io::Error),
+    VersionNotInstalled(String, String),
+    InconsistencyNotFound(String),
+    QueueFailed(String),
+    Remote(RemoteAccessError),
+    Database(DatabaseError),
 }
 impl Display for LibraryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -14,6 +34,83 @@ impl Display for LibraryError {
                 "Could not locate any installed version of game ID {} in the database",
                 id
             ),
+            LibraryError::GameRunning(id) => {
+                write!(f, "Game ID {} is running and cannot be modified", id)
+            }
+            LibraryError::GameBusy(id) => write!(
+                f,
+                "Game ID {} is currently downloading, updating or being modified",
+                id
+            ),
+            LibraryError::UninstallFailed(id, error) => {
+                write!(f, "Failed to uninstall game ID {}: {}", id, error)
+            }
+            LibraryError::MoveFailed(id, error) => {
+                write!(f, "Failed to move game ID {}: {}", id, error)
+            }
+            LibraryError::InvalidInstallDir(index) => {
+                write!(f, "Install directory {} does not exist", index)
+            }
+            LibraryError::OrphanNotFound(path) => write!(
+                f,
+                "{} is not orphaned data, or no longer exists",
+                path.display()
+            ),
+            LibraryError::OrphanNotAdoptable(path) => write!(
+                f,
+                "{} can't be picked back up, as another version of the game is installed where it would go",
+                path.display()
+            ),
+            LibraryError::NoUpdateAvailable(id) => {
+                write!(f, "There is no update available for game ID {}", id)
+            }
+            LibraryError::VersionPinned(id, version) => write!(
+                f,
+                "Game ID {} is pinned to version {}, so it can't be updated to anything else",
+                id, version
+            ),
+            LibraryError::NoRollbackAvailable(id) => write!(
+                f,
+                "There is no previous version of game ID {} to roll back to",
+                id
+            ),
+            LibraryError::RollbackFailed(id, error) => {
+                write!(f, "Failed to roll back game ID {}: {}", id, error)
+            }
+            LibraryError::InstallDirNotConfigured(path) => write!(
+                f,
+                "{} is no longer one of the configured install directories",
+                path.display()
+            ),
+            LibraryError::CleanupFailed(path, error) => {
+                write!(f, "Failed to delete {}: {}", path.display(), error)
+            }
+            LibraryError::VersionNotInstalled(id, version) => {
+                write!(f, "Version {} of game ID {} is not installed", version, id)
+            }
+            LibraryError::InconsistencyNotFound(id) => {
+                write!(f, "The data for game ID {} no longer needs fixing", id)
+            }
+            LibraryError::QueueFailed(id) => {
+                write!(
+                    f,
+                    "Could not queue game ID {}, as the download manager has stopped",
+                    id
+                )
+            }
+            LibraryError::Remote(error) => write!(f, "{}", error),
+            LibraryError::Database(error) => write!(f, "{}", error),
         }
     }
 }
+
+impl From<DatabaseError> for LibraryError {
+    fn from(value: DatabaseError) -> Self {
+        LibraryError::Database(value)
+    }
+}
+impl From<RemoteAccessError> for LibraryError {
+    fn from(value: RemoteAccessError) -> Self {
+        LibraryError::Remote(value)
+    }
+}
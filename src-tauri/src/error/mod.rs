@@ -1,6 +1,10 @@
 pub mod application_download_error;
+pub mod credential_error;
+pub mod database_error;
 pub mod drop_server_error;
 pub mod library_error;
 pub mod process_error;
+pub mod profile_error;
 pub mod remote_access_error;
+pub mod settings_error;
 pub mod setup_error;
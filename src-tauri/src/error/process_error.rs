@@ -2,6 +2,8 @@ use std::{fmt::Display, io::Error};
 
 use serde_with::SerializeDisplay;
 
+use super::database_error::DatabaseError;
+
 #[derive(SerializeDisplay)]
 pub enum ProcessError {
     SetupRequired,
@@ -12,6 +14,7 @@ pub enum ProcessError {
     InvalidVersion,
     IOError(Error),
     InvalidPlatform,
+    Database(DatabaseError),
 }
 
 impl Display for ProcessError {
@@ -25,7 +28,14 @@ impl Display for ProcessError {
             ProcessError::InvalidVersion => "Invalid Game version",
             ProcessError::IOError(error) => &error.to_string(),
             ProcessError::InvalidPlatform => "This Game cannot be played on the current platform",
+            ProcessError::Database(error) => &error.to_string(),
         };
         write!(f, "{}", s)
     }
 }
+
+impl From<DatabaseError> for ProcessError {
+    fn from(value: DatabaseError) -> Self {
+        ProcessError::Database(value)
+    }
+}
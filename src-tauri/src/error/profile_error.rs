@@ -0,0 +1,50 @@
+use std::fmt::Display;
+
+use serde_with::SerializeDisplay;
+
+use super::{database_error::DatabaseError, remote_access_error::RemoteAccessError};
+
+#[derive(SerializeDisplay)]
+pub enum ProfileError {
+    NotFound(String),
+    AlreadyExists(String),
+    InvalidName,
+    ActiveProfile(String),
+    InUse(String),
+    Remote(RemoteAccessError),
+    Database(DatabaseError),
+}
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::NotFound(name) => write!(f, "There is no server profile named {}", name),
+            ProfileError::AlreadyExists(name) => {
+                write!(f, "A server profile named {} already exists", name)
+            }
+            ProfileError::InvalidName => write!(f, "Server profile names cannot be empty"),
+            ProfileError::ActiveProfile(name) => write!(
+                f,
+                "{} is the active server profile. Switch to another one before removing it",
+                name
+            ),
+            ProfileError::InUse(name) => write!(
+                f,
+                "Games from server profile {} are still installed or downloading. Uninstall them before removing it",
+                name
+            ),
+            ProfileError::Remote(error) => write!(f, "{}", error),
+            ProfileError::Database(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<RemoteAccessError> for ProfileError {
+    fn from(value: RemoteAccessError) -> Self {
+        ProfileError::Remote(value)
+    }
+}
+impl From<DatabaseError> for ProfileError {
+    fn from(value: DatabaseError) -> Self {
+        ProfileError::Database(value)
+    }
+}
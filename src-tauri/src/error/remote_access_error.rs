@@ -4,11 +4,13 @@ use std::{
     sync::Arc,
 };
 
-use http::StatusCode;
 use serde_with::SerializeDisplay;
 use url::ParseError;
 
-use super::drop_server_error::DropServerError;
+use super::{
+    credential_error::CredentialError, database_error::DatabaseError,
+    drop_server_error::DropServerError,
+};
 
 #[derive(Debug, Clone, SerializeDisplay)]
 pub enum RemoteAccessError {
@@ -19,8 +21,14 @@ pub enum RemoteAccessError {
     GameNotFound,
     InvalidResponse(DropServerError),
     InvalidRedirect,
-    ManifestDownloadFailed(StatusCode, String),
+    ManifestDownloadFailed(usize, String),
     OutOfSync,
+    SigningFailed(String),
+    TlsError(String),
+    InvalidProxy(String),
+    ProfileNotFound(String),
+    Credentials(CredentialError),
+    Database(DatabaseError),
     Generic(String),
 }
 
@@ -51,6 +59,12 @@ impl Display for RemoteAccessError {
                 status, response
             ),
             RemoteAccessError::OutOfSync => write!(f, "server's and client's time are out of sync. Please ensure they are within at least 30 seconds of each other"),
+            RemoteAccessError::SigningFailed(message) => write!(f, "could not sign request: {}", message),
+            RemoteAccessError::TlsError(message) => write!(f, "could not establish a secure connection to the server: {}. If it uses a self-signed certificate, add its CA bundle or certificate fingerprint to the TLS settings for this server", message),
+            RemoteAccessError::InvalidProxy(message) => write!(f, "invalid proxy settings: {}", message),
+            RemoteAccessError::ProfileNotFound(name) => write!(f, "no server profile named {}", name),
+            RemoteAccessError::Credentials(error) => write!(f, "{}", error),
+            RemoteAccessError::Database(error) => write!(f, "{}", error),
             RemoteAccessError::Generic(message) => write!(f, "{}", message),
         }
     }
@@ -58,9 +72,39 @@ impl Display for RemoteAccessError {
 
 impl From<reqwest::Error> for RemoteAccessError {
     fn from(err: reqwest::Error) -> Self {
+        if err.is_connect() {
+            if let Some(reason) = tls_failure(&err) {
+                return RemoteAccessError::TlsError(reason);
+            }
+        }
         RemoteAccessError::FetchError(Arc::new(err))
     }
 }
+impl From<CredentialError> for RemoteAccessError {
+    fn from(err: CredentialError) -> Self {
+        RemoteAccessError::Credentials(err)
+    }
+}
+impl From<DatabaseError> for RemoteAccessError {
+    fn from(err: DatabaseError) -> Self {
+        RemoteAccessError::Database(err)
+    }
+}
+// reqwest doesn't tell TLS failures apart from other connection errors,
+// so look for one from the TLS backend further down the chain
+fn tls_failure(err: &reqwest::Error) -> Option<String> {
+    let mut source = err.source();
+    while let Some(error) = source {
+        if error.is::<openssl::ssl::Error>()
+            || error.is::<openssl::error::ErrorStack>()
+            || error.to_string().contains("certificate")
+        {
+            return Some(error.to_string());
+        }
+        source = error.source();
+    }
+    None
+}
 impl From<ParseError> for RemoteAccessError {
     fn from(err: ParseError) -> Self {
         RemoteAccessError::ParsingError(err)
@@ -0,0 +1,53 @@
+use std::{fmt::Display, path::PathBuf};
+
+use serde_with::SerializeDisplay;
+
+use super::{credential_error::CredentialError, database_error::DatabaseError};
+
+#[derive(SerializeDisplay, Debug)]
+pub enum SettingsError {
+    NotAnObject,
+    UnknownSetting(String),
+    InvalidValue(String),
+    OutOfRange(&'static str, u64, u64),
+    InvalidUrl(String, String),
+    InvalidFingerprint(String),
+    MissingFile(PathBuf),
+    Database(DatabaseError),
+    Credentials(CredentialError),
+}
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::NotAnObject => write!(f, "Settings must be sent as an object"),
+            SettingsError::UnknownSetting(key) => write!(f, "{} is not a setting", key),
+            SettingsError::InvalidValue(reason) => write!(f, "Invalid setting: {}", reason),
+            SettingsError::OutOfRange(setting, min, max) => {
+                write!(f, "{} must be between {} and {}", setting, min, max)
+            }
+            SettingsError::InvalidUrl(url, reason) => {
+                write!(f, "{} is not a valid address: {}", url, reason)
+            }
+            SettingsError::InvalidFingerprint(server) => write!(
+                f,
+                "The pinned fingerprint for {} must be a SHA-256 hash of its certificate",
+                server
+            ),
+            SettingsError::MissingFile(path) => write!(f, "{} does not exist", path.display()),
+            SettingsError::Database(error) => write!(f, "{}", error),
+            SettingsError::Credentials(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<DatabaseError> for SettingsError {
+    fn from(value: DatabaseError) -> Self {
+        SettingsError::Database(value)
+    }
+}
+
+impl From<CredentialError> for SettingsError {
+    fn from(value: CredentialError) -> Self {
+        SettingsError::Credentials(value)
+    }
+}
@@ -1,15 +1,27 @@
-use std::sync::Mutex;
+use std::{collections::HashSet, path::PathBuf, sync::Mutex, thread::spawn};
 
+use log::debug;
 use tauri::AppHandle;
 
 use crate::{
-    database::db::GameVersion, error::{library_error::LibraryError, remote_access_error::RemoteAccessError}, games::library::{get_current_meta, uninstall_game_logic}, AppState
+    database::db::{borrow_db_checked, GameVersion},
+    error::{
+        database_error::DatabaseError, library_error::LibraryError,
+        remote_access_error::RemoteAccessError,
+    },
+    games::{
+        library::{can_modify_game, get_current_meta, get_installed_meta, push_game_update},
+        orphans::{adopt_orphaned_data, delete_orphaned_data, scan_orphaned_data, OrphanedData},
+        reconcile::{confirm_fix, reconcile, Inconsistency},
+    },
+    update_checker::check_game_for_updates,
+    AppState,
 };
 
 use super::{
     library::{
-        fetch_game_logic, fetch_game_verion_options_logic, fetch_library_logic, FetchGameStruct,
-        Game,
+        fetch_game_logic, fetch_game_verion_options_logic, fetch_installed_versions_logic,
+        fetch_library_logic, set_default_version_logic, FetchGameStruct, Game, InstalledVersion,
     },
     state::{GameStatusManager, GameStatusWithTransient},
 };
@@ -28,22 +40,224 @@ pub fn fetch_game(
 }
 
 #[tauri::command]
-pub fn fetch_game_status(id: String) -> GameStatusWithTransient {
+pub fn fetch_game_status(id: String) -> Result<GameStatusWithTransient, DatabaseError> {
     GameStatusManager::fetch_state(&id)
 }
 
+/// Uninstalls the default version of a game, unless another version is given
+#[tauri::command]
+pub fn uninstall_game(
+    game_id: String,
+    version: Option<String>,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<(), LibraryError> {
+    let meta = match get_installed_meta(&game_id, version.clone())? {
+        Some(data) => data,
+        None => {
+            return Err(match version {
+                Some(version) => LibraryError::VersionNotInstalled(game_id, version),
+                None => LibraryError::MetaNotFound(game_id),
+            })
+        }
+    };
+    debug!("queueing uninstall for {:?}", meta);
+    state
+        .lock()
+        .unwrap()
+        .download_manager
+        .uninstall_application(meta);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn fetch_installed_versions(game_id: String) -> Result<Vec<InstalledVersion>, DatabaseError> {
+    fetch_installed_versions_logic(&game_id)
+}
+
+#[tauri::command]
+pub fn set_default_game_version(
+    game_id: String,
+    version: String,
+    state: tauri::State<'_, Mutex<AppState>>,
+    app_handle: AppHandle,
+) -> Result<(), LibraryError> {
+    let download_manager = state.lock().unwrap().download_manager.clone();
+    if download_manager
+        .read_queue()
+        .iter()
+        .any(|queued| queued.id == game_id)
+    {
+        return Err(LibraryError::GameBusy(game_id));
+    }
+
+    set_default_version_logic(&game_id, version, &app_handle)?;
+
+    // Updates are only ever checked for the default version
+    if let Some(meta) = get_current_meta(&game_id)? {
+        spawn(move || check_game_for_updates(&app_handle, &meta));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn move_game(
+    game_id: String,
+    target_install_dir: usize,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<(), LibraryError> {
+    let meta = match get_current_meta(&game_id)? {
+        Some(data) => data,
+        None => return Err(LibraryError::MetaNotFound(game_id)),
+    };
+    can_modify_game(&meta)?;
+
+    let target_base_dir = borrow_db_checked()?
+        .applications
+        .install_dirs
+        .get(target_install_dir)
+        .cloned()
+        .ok_or(LibraryError::InvalidInstallDir(target_install_dir))?;
+
+    let download_manager = state.lock().unwrap().download_manager.clone();
+    if download_manager
+        .read_queue()
+        .iter()
+        .any(|queued| queued.id == game_id)
+    {
+        return Err(LibraryError::GameBusy(game_id));
+    }
+
+    debug!("queueing move of {:?} to {:?}", meta, target_base_dir);
+    download_manager.move_application(meta, target_base_dir);
+
+    Ok(())
+}
+
 #[tauri::command]
-pub fn uninstall_game(game_id: String, app_handle: AppHandle) -> Result<(), LibraryError> {
-    let meta = match get_current_meta(&game_id) {
+pub fn rollback_game(
+    game_id: String,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<(), LibraryError> {
+    let meta = match get_current_meta(&game_id)? {
         Some(data) => data,
         None => return Err(LibraryError::MetaNotFound(game_id)),
     };
-    println!("{:?}", meta);
-    uninstall_game_logic(meta, &app_handle);
+    can_modify_game(&meta)?;
+    if !borrow_db_checked()?
+        .applications
+        .rollback_snapshots
+        .contains_key(&game_id)
+    {
+        return Err(LibraryError::NoRollbackAvailable(game_id));
+    }
+
+    let download_manager = state.lock().unwrap().download_manager.clone();
+    if download_manager
+        .read_queue()
+        .iter()
+        .any(|queued| queued.id == game_id)
+    {
+        return Err(LibraryError::GameBusy(game_id));
+    }
+
+    download_manager.rollback_application(meta);
+
+    Ok(())
+}
+
+/// The version a game would be rolled back to, if there is one
+#[tauri::command]
+pub fn fetch_rollback_version(game_id: String) -> Result<Option<String>, DatabaseError> {
+    Ok(borrow_db_checked()?
+        .applications
+        .rollback_snapshots
+        .get(&game_id)
+        .map(|snapshot| snapshot.version_name.clone()))
+}
+
+#[tauri::command]
+pub fn scan_orphaned_game_data(
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<Vec<OrphanedData>, DatabaseError> {
+    scan_orphaned_data(&queued_game_ids(&state))
+}
+
+/// Returns the number of bytes freed
+#[tauri::command]
+pub fn delete_orphaned_game_data(
+    path: PathBuf,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<u64, LibraryError> {
+    delete_orphaned_data(path, &queued_game_ids(&state))
+}
 
+/// Resumes or re-registers a partial install found by the orphan scan
+#[tauri::command]
+pub fn adopt_orphaned_game_data(
+    path: PathBuf,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<(), LibraryError> {
+    let download_manager = state.lock().unwrap().download_manager.clone();
+    adopt_orphaned_data(path, &queued_game_ids(&state), &download_manager)
+}
+
+fn queued_game_ids(state: &tauri::State<'_, Mutex<AppState>>) -> HashSet<String> {
+    let download_manager = state.lock().unwrap().download_manager.clone();
+    download_manager
+        .read_queue()
+        .into_iter()
+        .map(|meta| meta.id)
+        .collect()
+}
+
+/// Checks the game data in the database against itself and the disk,
+/// fixing whatever can be fixed without asking
+#[tauri::command]
+pub fn reconcile_game_data(
+    state: tauri::State<'_, Mutex<AppState>>,
+    app_handle: AppHandle,
+) -> Result<Vec<Inconsistency>, DatabaseError> {
+    let found = reconcile(&busy_game_ids(&state))?;
+
+    let fixed_game_ids: HashSet<&String> = found
+        .iter()
+        .filter(|inconsistency| inconsistency.fixed)
+        .map(|inconsistency| &inconsistency.game_id)
+        .collect();
+    for game_id in fixed_game_ids {
+        push_game_update(
+            &app_handle,
+            game_id,
+            GameStatusManager::fetch_state(game_id)?,
+        );
+    }
+    Ok(found)
+}
+
+#[tauri::command]
+pub fn fix_game_data(
+    inconsistency: Inconsistency,
+    state: tauri::State<'_, Mutex<AppState>>,
+    app_handle: AppHandle,
+) -> Result<(), LibraryError> {
+    confirm_fix(&inconsistency, &busy_game_ids(&state))?;
+    push_game_update(
+        &app_handle,
+        &inconsistency.game_id,
+        GameStatusManager::fetch_state(&inconsistency.game_id)?,
+    );
     Ok(())
 }
 
+// Queued games, and games that are running
+fn busy_game_ids(state: &tauri::State<'_, Mutex<AppState>>) -> HashSet<String> {
+    let mut busy_game_ids = queued_game_ids(state);
+    let process_manager = state.lock().unwrap().process_manager.clone();
+    busy_game_ids.extend(process_manager.lock().unwrap().running_game_ids().cloned());
+    busy_game_ids
+}
+
 #[tauri::command]
 pub fn fetch_game_verion_options(
     game_id: String,
@@ -1,10 +1,19 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    thread::spawn,
+};
+
+use tauri::AppHandle;
 
 use crate::{
+    database::db::{borrow_db_checked, borrow_db_mut_checked, save_db, UpdatePolicy},
     download_manager::{
         download_manager::DownloadManagerSignal, downloadable::Downloadable,
         internal_error::InternalError,
     },
+    error::{database_error::DatabaseError, library_error::LibraryError},
+    games::library::get_current_meta,
+    update_checker::{check_game_for_updates, queue_game_update},
     AppState,
 };
 
@@ -15,6 +24,7 @@ pub fn download_game(
     game_id: String,
     game_version: String,
     install_dir: usize,
+    side_by_side: Option<bool>,
     state: tauri::State<'_, Mutex<AppState>>,
 ) -> Result<(), InternalError<DownloadManagerSignal>> {
     let sender = state.lock().unwrap().download_manager.get_sender();
@@ -22,11 +32,53 @@ pub fn download_game(
         game_id,
         game_version,
         install_dir,
+        side_by_side.unwrap_or(false),
         sender,
-    )) as Box<dyn Downloadable + Send + Sync>);
+    )?) as Box<dyn Downloadable + Send + Sync>);
     Ok(state
         .lock()
         .unwrap()
         .download_manager
         .queue_download(game_download_agent)?)
 }
+
+#[tauri::command]
+pub fn update_game(
+    game_id: String,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<(), LibraryError> {
+    let download_manager = state.lock().unwrap().download_manager.clone();
+    queue_game_update(&game_id, &download_manager)
+}
+
+#[tauri::command]
+pub fn fetch_game_update_policy(game_id: String) -> Result<UpdatePolicy, DatabaseError> {
+    Ok(borrow_db_checked()?
+        .applications
+        .update_policies
+        .get(&game_id)
+        .cloned()
+        .unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn set_game_update_policy(
+    game_id: String,
+    policy: UpdatePolicy,
+    app_handle: AppHandle,
+) -> Result<(), DatabaseError> {
+    let mut db_lock = borrow_db_mut_checked()?;
+    db_lock
+        .applications
+        .update_policies
+        .insert(game_id.clone(), policy);
+    drop(db_lock);
+    save_db()?;
+
+    // Re-check straight away, so pinning (or unpinning)
+    // is reflected without waiting for the next check
+    if let Some(meta) = get_current_meta(&game_id)? {
+        spawn(move || check_game_for_updates(&app_handle, &meta));
+    }
+    Ok(())
+}
@@ -1,9 +1,10 @@
-use crate::auth::generate_authorization_header;
 use crate::database::db::{
-    borrow_db_checked, set_game_status, ApplicationTransientStatus, DatabaseImpls,
-    GameDownloadStatus,
+    borrow_db_checked, set_game_status, ApplicationTransientStatus, GameDownloadStatus,
+};
+use crate::download_manager::download_concurrency::{DownloadConcurrency, MAX_DOWNLOAD_THREADS};
+use crate::download_manager::download_manager::{
+    CancelPolicy, DownloadManagerSignal, DownloadStatus,
 };
-use crate::download_manager::download_manager::{DownloadManagerSignal, DownloadStatus};
 use crate::download_manager::download_thread_control_flag::{
     DownloadThreadControl, DownloadThreadControlFlag,
 };
@@ -11,39 +12,89 @@ use crate::download_manager::downloadable::Downloadable;
 use crate::download_manager::downloadable_metadata::{DownloadType, DownloadableMetadata};
 use crate::download_manager::progress_object::{ProgressHandle, ProgressObject};
 use crate::error::application_download_error::ApplicationDownloadError;
+use crate::error::database_error::DatabaseError;
 use crate::error::remote_access_error::RemoteAccessError;
 use crate::games::downloads::manifest::{DropDownloadContext, DropManifest};
-use crate::games::library::{on_game_complete, push_game_update, GameUpdateEvent};
-use crate::remote::requests::make_request;
-use crate::DB;
-use log::{debug, error, info};
-use rayon::ThreadPoolBuilder;
+use crate::games::library::{
+    get_installed_status, installed_statuses, on_game_complete, push_game_update, side_by_side_dir,
+    GameUpdateEvent,
+};
+use crate::games::rollback::ensure_rollback_snapshot;
+use crate::remote::client::async_remote_client;
+use crate::remote::requests::{
+    make_async_request_with_base, make_profile_request, profile_base_url, send_request,
+};
+use log::{debug, error, info, warn};
+use reqwest::{header::ACCEPT_ENCODING, RequestBuilder};
 use slice_deque::SliceDeque;
-use std::fs::{create_dir_all, File};
-use std::path::Path;
+use std::collections::{HashSet, VecDeque};
+use std::fs::{create_dir_all, remove_dir_all, remove_file, OpenOptions};
+use std::io::ErrorKind;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter};
+use tokio::fs::try_exists;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use url::Url;
 use urlencoding::encode;
 
 #[cfg(target_os = "linux")]
 use rustix::fs::{fallocate, FallocateFlags};
 
-use super::download_logic::download_game_chunk;
+use super::download_logic::{
+    copy_game_chunk, depot_source_path, download_game_chunk, CHUNK_ACCEPT_ENCODING,
+};
+use super::mirrors::{MirrorPool, PRIMARY_MIRROR};
 use super::stored_manifest::StoredManifest;
 
+/// Threads that drive the network and hash chunks. Every in-flight chunk
+/// is a task on these rather than a thread of its own
+const DOWNLOAD_WORKER_THREADS: usize = 4;
+/// Threads tokio hands file writes off to
+const DOWNLOAD_BLOCKING_THREADS: usize = 8;
+/// Times a chunk is tried before the whole download fails. Moving on to
+/// another mirror doesn't count, as that's a different server
+const CHUNK_ATTEMPTS: u32 = 3;
+/// Multiplied by the attempt, so each retry waits a little longer
+const CHUNK_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Shared by every download, as pooled connections in the cached async
+/// clients belong to the runtime they were opened on
+static DOWNLOAD_RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+    Builder::new_multi_thread()
+        .worker_threads(DOWNLOAD_WORKER_THREADS)
+        .max_blocking_threads(DOWNLOAD_BLOCKING_THREADS)
+        .thread_name("download-worker")
+        .enable_all()
+        .build()
+        .expect("failed to start download runtime")
+});
+
 pub struct GameDownloadAgent {
     pub id: String,
     pub version: String,
+    // The server profile the game is downloaded from, and what it calls the game
+    profile: String,
+    server_id: String,
     pub control_flag: DownloadThreadControl,
     contexts: Mutex<Vec<DropDownloadContext>>,
     completed_contexts: Mutex<SliceDeque<usize>>,
     pub manifest: Mutex<Option<DropManifest>>,
+    mirrors: Mutex<Option<Vec<Url>>>,
+    depot: Option<PathBuf>,
     pub progress: Arc<ProgressObject>,
     sender: Sender<DownloadManagerSignal>,
     pub stored_manifest: StoredManifest,
     status: Mutex<DownloadStatus>,
+    // The version this download writes over in place, if any
+    replaced_version: Mutex<Option<String>>,
 }
 
 impl GameDownloadAgent {
@@ -51,39 +102,66 @@ impl GameDownloadAgent {
         id: String,
         version: String,
         target_download_dir: usize,
+        side_by_side: bool,
         sender: Sender<DownloadManagerSignal>,
-    ) -> Self {
+    ) -> Result<Self, DatabaseError> {
         // Don't run by default
         let control_flag = DownloadThreadControl::new(DownloadThreadControlFlag::Stop);
 
-        let db_lock = borrow_db_checked();
+        let db_lock = borrow_db_checked()?;
         let base_dir = db_lock.applications.install_dirs[target_download_dir].clone();
-        drop(db_lock);
+        let depot = db_lock.applications.depots.get(&base_dir).cloned();
+        let profile = db_lock.game_profile(&id).to_string();
+        let server_id = db_lock.server_game_id(&id).to_string();
 
         let base_dir_path = Path::new(&base_dir);
-        let data_base_dir_path = base_dir_path.join(id.clone());
+        let default = get_installed_status(&db_lock.applications, &id, None);
+        let default_version = default.as_ref().and_then(|status| status.version_name());
+        let default_dir = default
+            .as_ref()
+            .and_then(|status| status.install_dir())
+            .map(Path::new);
+        let data_base_dir_path = match default_dir {
+            // Other versions go next to the default one
+            Some(_) if side_by_side && default_version != Some(&version) => {
+                side_by_side_dir(base_dir_path, &id, &version)
+            }
+            // Anything else replaces the default version in place
+            Some(default_dir) if default_dir.parent() == Some(base_dir_path) => {
+                default_dir.to_path_buf()
+            }
+            _ => base_dir_path.join(id.clone()),
+        };
+        drop(db_lock);
 
         let stored_manifest =
             StoredManifest::generate(id.clone(), version.clone(), data_base_dir_path.clone());
 
-        Self {
+        Ok(Self {
             id,
             version,
+            profile,
+            server_id,
             control_flag,
             manifest: Mutex::new(None),
+            mirrors: Mutex::new(None),
+            depot,
             contexts: Mutex::new(Vec::new()),
             completed_contexts: Mutex::new(SliceDeque::new()),
             progress: Arc::new(ProgressObject::new(0, 0, sender.clone())),
             sender,
             stored_manifest,
             status: Mutex::new(DownloadStatus::Queued),
-        }
+            replaced_version: Mutex::new(None),
+        })
     }
 
     // Blocking
     pub fn setup_download(&self) -> Result<(), ApplicationDownloadError> {
         self.ensure_manifest_exists()?;
 
+        self.ensure_mirrors();
+
         self.ensure_contexts()?;
 
         self.control_flag.set(DownloadThreadControlFlag::Go);
@@ -93,19 +171,27 @@ impl GameDownloadAgent {
 
     // Blocking
     pub fn download(&self, app_handle: &AppHandle) -> Result<bool, ApplicationDownloadError> {
+        let replaced_version = self.find_replaced_version()?;
+        // Has to happen before setup, which starts resizing files
+        if let Some(previous_version_name) = &replaced_version {
+            ensure_rollback_snapshot(app_handle, &self.id)
+                .map_err(|e| ApplicationDownloadError::IoError(e.kind()))?;
+            self.mark_partially_installed(app_handle, previous_version_name)?;
+        }
+        *self.replaced_version.lock().unwrap() = replaced_version.clone();
         self.setup_download()?;
         self.set_progress_object_params();
         let timer = Instant::now();
-        push_game_update(
-            app_handle,
-            &self.metadata().id,
-            (
-                None,
-                Some(ApplicationTransientStatus::Downloading {
-                    version_name: self.version.clone(),
-                }),
-            ),
-        );
+        let status = if replaced_version.is_some() {
+            ApplicationTransientStatus::Updating {
+                version_name: self.version.clone(),
+            }
+        } else {
+            ApplicationTransientStatus::Downloading {
+                version_name: self.version.clone(),
+            }
+        };
+        push_game_update(app_handle, &self.metadata().id, (None, Some(status), None));
         let res = self
             .run()
             .map_err(|_| ApplicationDownloadError::DownloadError);
@@ -118,6 +204,54 @@ impl GameDownloadAgent {
         res
     }
 
+    /// The version this download replaces in the same place, if any. For an
+    /// update that was interrupted, that's still the version it started from
+    fn find_replaced_version(&self) -> Result<Option<String>, DatabaseError> {
+        let db_lock = borrow_db_checked()?;
+        Ok(match db_lock.applications.game_statuses.get(&self.id) {
+            Some(GameDownloadStatus::Installed {
+                version_name,
+                install_dir,
+            })
+            | Some(GameDownloadStatus::SetupRequired {
+                version_name,
+                install_dir,
+            }) if *version_name != self.version
+                && Path::new(install_dir) == self.stored_manifest.base_path =>
+            {
+                Some(version_name.clone())
+            }
+            Some(GameDownloadStatus::PartiallyInstalled {
+                install_dir,
+                previous_version_name,
+                ..
+            }) if Path::new(install_dir) == self.stored_manifest.base_path => {
+                Some(previous_version_name.clone())
+            }
+            _ => None,
+        })
+    }
+
+    /// Stops the version being written over from counting as installed,
+    /// until on_game_complete marks the new one as installed
+    fn mark_partially_installed(
+        &self,
+        app_handle: &AppHandle,
+        previous_version_name: &str,
+    ) -> Result<(), DatabaseError> {
+        let status = GameDownloadStatus::PartiallyInstalled {
+            version_name: self.version.clone(),
+            install_dir: self.stored_manifest.base_path.to_string_lossy().to_string(),
+            previous_version_name: previous_version_name.to_string(),
+        };
+        set_game_status(app_handle, self.metadata(), |db_handle, meta| {
+            db_handle
+                .applications
+                .game_statuses
+                .insert(meta.id.clone(), status);
+        })
+    }
+
     pub fn ensure_manifest_exists(&self) -> Result<(), ApplicationDownloadError> {
         if self.manifest.lock().unwrap().is_some() {
             return Ok(());
@@ -127,28 +261,7 @@ impl GameDownloadAgent {
     }
 
     fn download_manifest(&self) -> Result<(), ApplicationDownloadError> {
-        let header = generate_authorization_header();
-        let client = reqwest::blocking::Client::new();
-        let response = make_request(
-            &client,
-            &["/api/v1/client/game/manifest"],
-            &[("id", &self.id), ("version", &self.version)],
-            |f| f.header("Authorization", header),
-        )
-        .map_err(|e| ApplicationDownloadError::Communication(e))?
-        .send()
-        .map_err(|e| ApplicationDownloadError::Communication(e.into()))?;
-
-        if response.status() != 200 {
-            return Err(ApplicationDownloadError::Communication(
-                RemoteAccessError::ManifestDownloadFailed(
-                    response.status(),
-                    response.text().unwrap(),
-                ),
-            ));
-        }
-
-        let manifest_download: DropManifest = response.json().unwrap();
+        let manifest_download = self.fetch_manifest(&self.version)?;
 
         if let Ok(mut manifest) = self.manifest.lock() {
             *manifest = Some(manifest_download);
@@ -158,6 +271,114 @@ impl GameDownloadAgent {
         Err(ApplicationDownloadError::Lock)
     }
 
+    fn fetch_manifest(&self, version: &str) -> Result<DropManifest, ApplicationDownloadError> {
+        let request = make_profile_request(
+            &self.profile,
+            &["/api/v1/client/game/manifest"],
+            &[("id", &self.server_id), ("version", version)],
+            |f| f,
+        )
+        .map_err(ApplicationDownloadError::Communication)?;
+        let response = send_request(request).map_err(|e| {
+            ApplicationDownloadError::Communication(match e {
+                RemoteAccessError::InvalidResponse(err) => {
+                    RemoteAccessError::ManifestDownloadFailed(err.status_code, err.message)
+                }
+                e => e,
+            })
+        })?;
+
+        response
+            .json()
+            .map_err(|e| ApplicationDownloadError::Communication(e.into()))
+    }
+
+    /// Deletes the files of the replaced version that the new one doesn't
+    /// have. Only files from its manifest are touched, so anything the game
+    /// created itself, like saves, is left alone
+    fn remove_stale_files(&self, previous_version_name: &str) {
+        let previous_manifest = match self.fetch_manifest(previous_version_name) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!(
+                    "could not fetch manifest of {} {}, leaving its files in place: {}",
+                    self.id, previous_version_name, e
+                );
+                return;
+            }
+        };
+        let manifest = self.manifest.lock().unwrap().clone().unwrap_or_default();
+
+        for raw_path in previous_manifest.keys() {
+            if manifest.contains_key(raw_path) {
+                continue;
+            }
+            let path = self.stored_manifest.base_path.join(Path::new(raw_path));
+            match remove_file(&path) {
+                Ok(()) => debug!("removed stale file {}", path.display()),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => warn!("could not remove stale file {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    pub fn ensure_mirrors(&self) {
+        if self.mirrors.lock().unwrap().is_some() {
+            return;
+        }
+
+        // Mirrors are optional, so any failure just means
+        // everything comes from the primary server
+        let mirrors = match self.fetch_mirrors() {
+            Ok(mirrors) => mirrors,
+            Err(e) => {
+                warn!("could not fetch mirrors for {}: {}", self.id, e);
+                Vec::new()
+            }
+        };
+        if !mirrors.is_empty() {
+            info!("using {} mirrors for {}", mirrors.len(), self.id);
+        }
+
+        *self.mirrors.lock().unwrap() = Some(mirrors);
+    }
+
+    fn fetch_mirrors(&self) -> Result<Vec<Url>, RemoteAccessError> {
+        let request = make_profile_request(
+            &self.profile,
+            &["/api/v1/client/game/mirrors"],
+            &[("id", &self.server_id), ("version", &self.version)],
+            |f| f,
+        )?;
+        let response = match send_request(request) {
+            Ok(response) => response,
+            // Older servers don't know about mirrors at all
+            Err(RemoteAccessError::InvalidResponse(err)) if err.status_code == 404 => {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mirrors: Vec<String> = response.json()?;
+        Ok(mirrors
+            .into_iter()
+            .filter_map(|mirror| match Url::parse(&mirror) {
+                // Without the trailing slash, the last path segment
+                // would be replaced when the chunk path is joined on
+                Ok(mut url) => {
+                    if !url.path().ends_with('/') {
+                        url.set_path(&format!("{}/", url.path()));
+                    }
+                    Some(url)
+                }
+                Err(e) => {
+                    warn!("ignoring invalid mirror {}: {}", mirror, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
     fn set_progress_object_params(&self) {
         // Avoid re-setting it
         if self.progress.get_max() != 0 {
@@ -186,7 +407,8 @@ impl GameDownloadAgent {
 
     pub fn generate_contexts(&self) -> Result<(), ApplicationDownloadError> {
         let manifest = self.manifest.lock().unwrap().clone().unwrap();
-        let game_id = self.id.clone();
+        // Chunks are fetched from the server (or a depot laid out like it)
+        let game_id = self.server_id.clone();
 
         let mut contexts = Vec::new();
         let base_path = Path::new(&self.stored_manifest.base_path);
@@ -205,7 +427,13 @@ impl GameDownloadAgent {
             let container = path.parent().unwrap();
             create_dir_all(container).unwrap();
 
-            let file = File::create(path.clone()).unwrap();
+            // Don't truncate, as we may be resuming a partial download
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path.clone())
+                .unwrap();
             let mut running_offset = 0;
 
             for (index, length) in chunk.lengths.iter().enumerate() {
@@ -223,6 +451,10 @@ impl GameDownloadAgent {
                 running_offset += *length as u64;
             }
 
+            // Updates write over the previous version, which
+            // may have left a longer file behind
+            file.set_len(running_offset).unwrap();
+
             #[cfg(target_os = "linux")]
             if running_offset > 0 {
                 let _ = fallocate(file, FallocateFlags::empty(), 0, running_offset);
@@ -235,78 +467,109 @@ impl GameDownloadAgent {
 
     // TODO: Change return value on Err
     pub fn run(&self) -> Result<bool, ()> {
-        let max_download_threads = borrow_db_checked().settings.max_download_threads;
+        let adaptive_download_threads = {
+            let Ok(db_lock) = borrow_db_checked() else {
+                return Err(());
+            };
+            db_lock.settings.adaptive_download_threads
+        };
+        let concurrency =
+            DownloadConcurrency::new(adaptive_download_threads, self.progress.clone());
 
         debug!(
-            "downloading game: {} with {} threads",
-            self.id, max_download_threads
+            "downloading game: {} with {} threads (adaptive: {})",
+            self.id,
+            concurrency.limit(),
+            adaptive_download_threads
         );
-        let pool = ThreadPoolBuilder::new()
-            .num_threads(max_download_threads)
-            .build()
-            .unwrap();
 
-        let completed_indexes = Arc::new(boxcar::Vec::new());
-        let completed_indexes_loop_arc = completed_indexes.clone();
+        // Clients can't be built from inside the runtime. A mirror we
+        // can't build one for is left out, but the primary server is needed
+        let primary = profile_base_url(&self.profile);
+        let primary_client = primary
+            .clone()
+            .and_then(|primary| async_remote_client(&primary));
+        let (primary, mut clients) = match (primary, primary_client) {
+            (Ok(primary), Ok(client)) => (primary, vec![client]),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("{}", e);
+                self.sender
+                    .send(DownloadManagerSignal::Error(
+                        ApplicationDownloadError::Communication(e),
+                    ))
+                    .unwrap();
+                return Ok(false);
+            }
+        };
+        let mut mirror_urls = Vec::new();
+        for mirror in self.mirrors.lock().unwrap().clone().unwrap_or_default() {
+            match async_remote_client(&mirror) {
+                Ok(client) => {
+                    clients.push(client);
+                    mirror_urls.push(mirror);
+                }
+                Err(e) => warn!("not using mirror {}: {}", mirror, e),
+            }
+        }
 
-        let contexts = self.contexts.lock().unwrap();
-        pool.scope(|scope| {
-            let client = &reqwest::blocking::Client::new();
+        let contexts = self.contexts.lock().unwrap().clone();
+
+        let pending = {
+            let completed_contexts: HashSet<usize> = self
+                .completed_contexts
+                .lock()
+                .unwrap()
+                .iter()
+                .copied()
+                .collect();
+            let mut pending = VecDeque::new();
             for (index, context) in contexts.iter().enumerate() {
-                let client = client.clone();
-                let completed_indexes = completed_indexes_loop_arc.clone();
-
-                let progress = self.progress.get(index);
-                let progress_handle = ProgressHandle::new(progress, self.progress.clone());
-
                 // If we've done this one already, skip it
-                if self.completed_contexts.lock().unwrap().contains(&index) {
-                    progress_handle.skip(context.length);
+                if completed_contexts.contains(&index) {
+                    ProgressHandle::new(self.progress.get(index), self.progress.clone())
+                        .skip(context.length);
                     continue;
                 }
+                pending.push_back(index);
+            }
+            Mutex::new(pending)
+        };
 
-                let sender = self.sender.clone();
-
-                let request = match make_request(
-                    &client,
-                    &["/api/v1/client/chunk"],
-                    &[
-                        ("id", &context.game_id),
-                        ("version", &context.version),
-                        ("name", &context.file_name),
-                        ("chunk", &context.index.to_string()),
-                    ],
-                    |r| r.header("Authorization", generate_authorization_header()),
-                ) {
-                    Ok(request) => request,
-                    Err(e) => {
-                        sender.send(DownloadManagerSignal::Error(ApplicationDownloadError::Communication(e))).unwrap();
-                        continue;
-                    },
-                };
-
-                scope.spawn(move |_| {
-                    match download_game_chunk(context, &self.control_flag, progress_handle, request)
-                    {
-                        Ok(res) => {
-                            if res {
-                                completed_indexes.push(index);
-                            }
-                        }
-                        Err(e) => {
-                            error!("{}", e);
-                            sender.send(DownloadManagerSignal::Error(e)).unwrap();
-                        }
+        let jobs = Arc::new(ChunkJobs {
+            attempts: contexts.iter().map(|_| AtomicU32::new(0)).collect(),
+            contexts,
+            pending,
+            completed: boxcar::Vec::new(),
+            failed: AtomicBool::new(false),
+            concurrency,
+            mirrors: MirrorPool::new(primary, mirror_urls, self.progress.clone()),
+            clients,
+            profile: self.profile.clone(),
+            depot: self.depot.clone(),
+            control_flag: self.control_flag.clone(),
+            progress: self.progress.clone(),
+            sender: self.sender.clone(),
+        });
+
+        DOWNLOAD_RUNTIME.block_on(async {
+            let mut workers = JoinSet::new();
+            // Workers beyond the current limit wait for a permit, so the
+            // limit can be raised while the download is running
+            for _ in 0..MAX_DOWNLOAD_THREADS {
+                workers.spawn(run_chunk_worker(jobs.clone()));
+            }
+            while let Some(result) = workers.join_next().await {
+                if let Err(e) = result {
+                    if e.is_panic() {
+                        panic::resume_unwind(e.into_panic());
                     }
-                });
+                }
             }
         });
 
-        let newly_completed = completed_indexes.to_owned();
-
         let completed_lock_len = {
             let mut completed_contexts_lock = self.completed_contexts.lock().unwrap();
-            for (_, item) in newly_completed.iter() {
+            for (_, item) in jobs.completed.iter() {
                 completed_contexts_lock.push_front(*item);
             }
 
@@ -314,12 +577,12 @@ impl GameDownloadAgent {
         };
 
         // If we're not out of contexts, we're not done, so we don't fire completed
-        if completed_lock_len != contexts.len() {
+        if completed_lock_len != jobs.contexts.len() {
             info!(
                 "download agent for {} exited without completing ({}/{})",
                 self.id.clone(),
                 completed_lock_len,
-                contexts.len(),
+                jobs.contexts.len(),
             );
             self.stored_manifest
                 .set_completed_contexts(self.completed_contexts.lock().unwrap().as_slice());
@@ -327,6 +590,10 @@ impl GameDownloadAgent {
             return Ok(false);
         }
 
+        if let Some(previous_version_name) = self.replaced_version.lock().unwrap().as_ref() {
+            self.remove_stale_files(previous_version_name);
+        }
+
         // We've completed
         self.sender
             .send(DownloadManagerSignal::Completed(self.metadata()))
@@ -336,6 +603,201 @@ impl GameDownloadAgent {
     }
 }
 
+/// Everything the chunk workers of a single run share
+struct ChunkJobs {
+    contexts: Vec<DropDownloadContext>,
+    // Only the indexes are queued up front. Each worker takes the next
+    // one and signs its request right before sending it, so nonces
+    // don't expire while they wait in the queue.
+    pending: Mutex<VecDeque<usize>>,
+    completed: boxcar::Vec<usize>,
+    // Failed attempts at each chunk, by index
+    attempts: Vec<AtomicU32>,
+    failed: AtomicBool,
+    concurrency: DownloadConcurrency,
+    mirrors: MirrorPool,
+    // One per mirror, in the same order as the MirrorPool
+    clients: Vec<reqwest::Client>,
+    profile: String,
+    depot: Option<PathBuf>,
+    control_flag: DownloadThreadControl,
+    progress: Arc<ProgressObject>,
+    sender: Sender<DownloadManagerSignal>,
+}
+
+impl ChunkJobs {
+    /// Stops every worker from taking on new chunks
+    fn fail(&self, e: ApplicationDownloadError) {
+        error!("{}", e);
+        self.failed.store(true, Ordering::Relaxed);
+        self.sender.send(DownloadManagerSignal::Error(e)).unwrap();
+    }
+}
+
+async fn run_chunk_worker(jobs: Arc<ChunkJobs>) {
+    loop {
+        let permit = jobs.concurrency.acquire().await;
+
+        // Stopping (pausing, cancelling or failing) just means
+        // we don't take on any new work
+        if jobs.control_flag.get() == DownloadThreadControlFlag::Stop
+            || jobs.failed.load(Ordering::Relaxed)
+        {
+            break;
+        }
+        let next = jobs.pending.lock().unwrap().pop_front();
+        let Some(index) = next else {
+            break;
+        };
+        let context = &jobs.contexts[index];
+        let progress_handle = ProgressHandle::new(jobs.progress.get(index), jobs.progress.clone());
+
+        // Depots are preferred, but anything missing
+        // from them still comes from the server
+        if let Some(depot) = &jobs.depot {
+            if try_exists(depot_source_path(depot, context))
+                .await
+                .unwrap_or(false)
+            {
+                match copy_game_chunk(context, depot, &jobs.control_flag, progress_handle).await {
+                    Ok(res) => {
+                        if res {
+                            jobs.completed.push(index);
+                        }
+                    }
+                    Err(e) => {
+                        jobs.concurrency.record_error();
+                        jobs.fail(e);
+                        break;
+                    }
+                }
+                continue;
+            }
+        }
+
+        let (mirror, base_url) = jobs.mirrors.select();
+        // Signatures can be replayed, so they only ever go to our own server
+        let profile = (mirror == PRIMARY_MIRROR).then_some(jobs.profile.as_str());
+        let request = match make_chunk_request(&jobs.clients[mirror], profile, base_url, context) {
+            Ok(request) => request,
+            Err(e) => {
+                jobs.fail(ApplicationDownloadError::Communication(e));
+                break;
+            }
+        };
+
+        let chunk_start = Instant::now();
+        match download_game_chunk(context, &jobs.control_flag, progress_handle, request).await {
+            Ok(res) => {
+                if res {
+                    jobs.mirrors
+                        .record_success(mirror, context.length, chunk_start.elapsed());
+                    jobs.completed.push(index);
+                }
+            }
+            Err(e) => {
+                jobs.concurrency.record_error();
+                if jobs.mirrors.record_failure(mirror) {
+                    // Throw away the partial chunk and give it to
+                    // whichever mirror is next in line
+                    warn!("retrying chunk {} after mirror error: {}", index, e);
+                    ProgressHandle::new(jobs.progress.get(index), jobs.progress.clone()).reset();
+                    jobs.pending.lock().unwrap().push_front(index);
+                    continue;
+                }
+
+                let attempt = jobs.attempts[index].fetch_add(1, Ordering::Relaxed) + 1;
+                if attempt < CHUNK_ATTEMPTS {
+                    warn!(
+                        "retrying chunk {} ({}/{}) after error: {}",
+                        index, attempt, CHUNK_ATTEMPTS, e
+                    );
+                    ProgressHandle::new(jobs.progress.get(index), jobs.progress.clone()).reset();
+                    // Other workers carry on in the meantime
+                    drop(permit);
+                    sleep(CHUNK_RETRY_DELAY * attempt).await;
+                    jobs.pending.lock().unwrap().push_back(index);
+                    continue;
+                }
+
+                jobs.fail(e);
+                break;
+            }
+        }
+    }
+}
+
+fn make_chunk_request(
+    client: &reqwest::Client,
+    profile: Option<&str>,
+    base_url: Url,
+    context: &DropDownloadContext,
+) -> Result<RequestBuilder, RemoteAccessError> {
+    // Relative, so a mirror's path prefix is kept
+    make_async_request_with_base(
+        client,
+        profile,
+        base_url,
+        &["api/v1/client/chunk"],
+        &[
+            ("id", &context.game_id),
+            ("version", &context.version),
+            ("name", &context.file_name),
+            ("chunk", &context.index.to_string()),
+        ],
+    )
+    .map(|request| request.header(ACCEPT_ENCODING, CHUNK_ACCEPT_ENCODING))
+}
+
+fn cleanup_cancelled_download(
+    app_handle: &AppHandle,
+    meta: DownloadableMetadata,
+    base_path: PathBuf,
+) {
+    // Never delete a directory that an installed version still lives in,
+    // which can't be ruled out without the database
+    let Ok(db_lock) = borrow_db_checked() else {
+        return;
+    };
+    let in_use = installed_statuses(&db_lock.applications, &meta.id)
+        .any(|status| status.install_dir().map(Path::new) == Some(base_path.as_path()));
+    drop(db_lock);
+
+    if in_use {
+        warn!(
+            "not deleting {} after cancel, as it belongs to an installed version of {}",
+            base_path.display(),
+            meta.id
+        );
+        let _ = set_game_status(app_handle, meta, |db_handle, meta| {
+            db_handle.applications.transient_statuses.remove(meta);
+        });
+        return;
+    }
+
+    if base_path.exists() {
+        if let Err(e) = remove_dir_all(&base_path) {
+            error!(
+                "failed to delete cancelled download at {}: {}",
+                base_path.display(),
+                e
+            );
+        }
+    }
+    debug!("removed cancelled download data for {}", meta.id);
+
+    let _ = set_game_status(app_handle, meta, |db_handle, meta| {
+        db_handle.applications.transient_statuses.remove(meta);
+        // Cancelling a side-by-side install leaves the default one alone
+        if get_installed_status(&db_handle.applications, &meta.id, None).is_none() {
+            db_handle
+                .applications
+                .game_statuses
+                .insert(meta.id.clone(), GameDownloadStatus::Remote {});
+        }
+    });
+}
+
 impl Downloadable for GameDownloadAgent {
     fn download(&self, app_handle: &AppHandle) -> Result<bool, ApplicationDownloadError> {
         *self.status.lock().unwrap() = DownloadStatus::Downloading;
@@ -370,18 +832,20 @@ impl Downloadable for GameDownloadAgent {
 
         error!("error while managing download: {}", error);
 
-        set_game_status(app_handle, self.metadata(), |db_handle, meta| {
+        let _ = set_game_status(app_handle, self.metadata(), |db_handle, meta| {
             db_handle.applications.transient_statuses.remove(meta);
         });
     }
 
     fn on_complete(&self, app_handle: &tauri::AppHandle) {
-        on_game_complete(
+        if let Err(e) = on_game_complete(
             &self.metadata(),
             self.stored_manifest.base_path.to_string_lossy().to_string(),
             app_handle,
-        )
-        .unwrap();
+        ) {
+            error!("could not finish installing {}: {}", self.id, e);
+            app_handle.emit("download_error", e.to_string()).unwrap();
+        }
     }
 
     // TODO: fix this function. It doesn't restart the download properly, nor does it reset the state properly
@@ -393,13 +857,36 @@ impl Downloadable for GameDownloadAgent {
                 &format!("update_game/{}", meta.id),
                 GameUpdateEvent {
                     game_id: meta.id.clone(),
-                    status: (Some(GameDownloadStatus::Remote {}), None),
+                    status: (Some(GameDownloadStatus::Remote {}), None, None),
                 },
             )
             .unwrap();
     }
 
-    fn on_cancelled(&self, _app_handle: &tauri::AppHandle) {}
+    fn on_cancelled(&self, app_handle: &tauri::AppHandle, policy: CancelPolicy) {
+        let meta = self.metadata();
+        match policy {
+            CancelPolicy::KeepPartial => {
+                // Make sure whatever we've finished so far gets picked up
+                // the next time this game is queued to the same directory.
+                // If we never started, whatever is on disk is already correct
+                if !self.contexts.lock().unwrap().is_empty() {
+                    self.stored_manifest
+                        .set_completed_contexts(self.completed_contexts.lock().unwrap().as_slice());
+                    self.stored_manifest.write();
+                }
+
+                let _ = set_game_status(app_handle, meta, |db_handle, meta| {
+                    db_handle.applications.transient_statuses.remove(meta);
+                });
+            }
+            CancelPolicy::Delete => {
+                let base_path = self.stored_manifest.base_path.clone();
+                let app_handle = app_handle.clone();
+                spawn(move || cleanup_cancelled_download(&app_handle, meta, base_path));
+            }
+        }
+    }
 
     fn status(&self) -> DownloadStatus {
         self.status.lock().unwrap().clone()
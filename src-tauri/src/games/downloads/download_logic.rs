@@ -5,78 +5,172 @@ use crate::download_manager::progress_object::ProgressHandle;
 use crate::error::application_download_error::ApplicationDownloadError;
 use crate::error::remote_access_error::RemoteAccessError;
 use crate::games::downloads::manifest::DropDownloadContext;
-use log::warn;
+use crate::remote::requests::send_async_request;
+use flate2::write::GzDecoder;
 use md5::{Context, Digest};
-use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::{header::CONTENT_ENCODING, RequestBuilder, Response};
 
-use std::fs::{set_permissions, Permissions};
-use std::io::{ErrorKind, Read};
+#[cfg(unix)]
+use std::fs::Permissions;
+use std::io::{ErrorKind, SeekFrom, Write};
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::{
     fs::{File, OpenOptions},
-    io::{self, BufWriter, Seek, SeekFrom, Write},
-    path::PathBuf,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter, Take},
 };
+use zstd::stream::write::Decoder as ZstdDecoder;
+
+/// Sent with chunk requests, in order of preference
+pub const CHUNK_ACCEPT_ENCODING: &str = "zstd, gzip";
 
-pub struct DropWriter<W: Write> {
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+pub struct DropWriter {
     hasher: Context,
-    destination: W,
+    destination: BufWriter<File>,
 }
-impl DropWriter<File> {
-    fn new(path: PathBuf) -> Self {
-        Self {
-            destination: OpenOptions::new().write(true).open(path).unwrap(),
-            hasher: Context::new(),
+impl DropWriter {
+    async fn open(path: &Path, offset: u64) -> io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).open(path).await?;
+        if offset != 0 {
+            file.seek(SeekFrom::Start(offset)).await?;
         }
+
+        Ok(Self {
+            destination: BufWriter::with_capacity(1024 * 1024, file),
+            hasher: Context::new(),
+        })
     }
 
-    fn finish(mut self) -> io::Result<Digest> {
-        self.flush().unwrap();
+    // Writes to the file and the hasher together
+    async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.hasher.consume(buf);
+        self.destination.write_all(buf).await
+    }
+
+    async fn finish(mut self) -> io::Result<Digest> {
+        // Unlike the std one, tokio's BufWriter doesn't flush on drop
+        self.destination.flush().await?;
         Ok(self.hasher.compute())
     }
 }
-// Write automatically pushes to file and hasher
-impl Write for DropWriter<File> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.hasher.write_all(buf).map_err(|e| {
-            io::Error::new(
-                ErrorKind::Other,
-                format!("Unable to write to hasher: {}", e),
-            )
-        })?;
-        self.destination.write(buf)
+
+/// Where a chunk's bytes come from
+pub enum ChunkSource {
+    Remote(Response),
+    Depot(Take<File>),
+}
+impl ChunkSource {
+    /// Replaces the contents of `buf` with the next piece of the chunk.
+    /// Returns false once there's nothing left
+    async fn next_piece(&mut self, buf: &mut Vec<u8>) -> io::Result<bool> {
+        buf.clear();
+        match self {
+            ChunkSource::Remote(response) => {
+                match response.chunk().await.map_err(io::Error::other)? {
+                    Some(bytes) => buf.extend_from_slice(&bytes),
+                    None => return Ok(false),
+                }
+            }
+            ChunkSource::Depot(file) => {
+                buf.resize(COPY_BUFFER_SIZE, 0);
+                let bytes_read = file.read(buf).await?;
+                buf.truncate(bytes_read);
+            }
+        }
+        Ok(!buf.is_empty())
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.hasher.flush()?;
-        self.destination.flush()
+    fn is_remote(&self) -> bool {
+        matches!(self, ChunkSource::Remote(_))
     }
 }
-// Seek moves around destination output
-impl Seek for DropWriter<File> {
-    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        self.destination.seek(pos)
+
+/// Undoes a chunk's Content-Encoding as it streams in
+pub enum ChunkDecoder {
+    Identity,
+    Gzip(Box<GzDecoder<Vec<u8>>>),
+    Zstd(Box<ZstdDecoder<'static, Vec<u8>>>),
+}
+impl ChunkDecoder {
+    fn for_encoding(encoding: Option<&str>) -> io::Result<Self> {
+        match encoding.map(str::trim) {
+            None | Some("identity") => Ok(ChunkDecoder::Identity),
+            Some("gzip") | Some("x-gzip") => {
+                Ok(ChunkDecoder::Gzip(Box::new(GzDecoder::new(Vec::new()))))
+            }
+            Some("zstd") => Ok(ChunkDecoder::Zstd(Box::new(ZstdDecoder::new(Vec::new())?))),
+            Some(encoding) => Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!("server sent a chunk with unsupported encoding {}", encoding),
+            )),
+        }
+    }
+
+    /// Feeds in bytes as they came off the wire, returning whatever
+    /// they could be decompressed into so far
+    fn decode<'a>(&'a mut self, input: &'a [u8]) -> io::Result<&'a [u8]> {
+        match self {
+            ChunkDecoder::Identity => Ok(input),
+            ChunkDecoder::Gzip(decoder) => {
+                decoder.get_mut().clear();
+                decoder.write_all(input)?;
+                decoder.flush()?;
+                Ok(decoder.get_ref())
+            }
+            ChunkDecoder::Zstd(decoder) => {
+                decoder.get_mut().clear();
+                decoder.write_all(input)?;
+                decoder.flush()?;
+                Ok(decoder.get_ref())
+            }
+        }
+    }
+
+    /// Whatever was still held back once the source has ended. For gzip,
+    /// this also checks the stream wasn't cut short
+    fn finish(&mut self) -> io::Result<&[u8]> {
+        match self {
+            ChunkDecoder::Identity => Ok(&[]),
+            ChunkDecoder::Gzip(decoder) => {
+                decoder.get_mut().clear();
+                decoder.try_finish()?;
+                Ok(decoder.get_ref())
+            }
+            ChunkDecoder::Zstd(decoder) => {
+                decoder.get_mut().clear();
+                decoder.flush()?;
+                Ok(decoder.get_ref())
+            }
+        }
     }
 }
 
-pub struct DropDownloadPipeline<'a, R: Read, W: Write> {
-    pub source: R,
-    pub destination: DropWriter<W>,
+pub struct DropDownloadPipeline<'a> {
+    pub source: ChunkSource,
+    pub decoder: ChunkDecoder,
+    pub destination: DropWriter,
     pub control_flag: &'a DownloadThreadControl,
     pub progress: ProgressHandle,
     pub size: usize,
 }
-impl<'a> DropDownloadPipeline<'a, Response, File> {
+impl<'a> DropDownloadPipeline<'a> {
     fn new(
-        source: Response,
-        destination: DropWriter<File>,
+        source: ChunkSource,
+        decoder: ChunkDecoder,
+        destination: DropWriter,
         control_flag: &'a DownloadThreadControl,
         progress: ProgressHandle,
         size: usize,
     ) -> Self {
         Self {
             source,
+            decoder,
             destination,
             control_flag,
             progress,
@@ -84,10 +178,10 @@ impl<'a> DropDownloadPipeline<'a, Response, File> {
         }
     }
 
-    fn copy(&mut self) -> Result<bool, io::Error> {
-        let copy_buf_size = 512;
-        let mut copy_buf = vec![0; copy_buf_size];
-        let mut buf_writer = BufWriter::with_capacity(1024 * 1024, &mut self.destination);
+    /// `size` is what the chunk takes up on disk. Compressed sources are
+    /// read until they end, as their length on the wire isn't known
+    async fn copy(&mut self) -> Result<bool, io::Error> {
+        let mut copy_buf = Vec::with_capacity(COPY_BUFFER_SIZE);
 
         let mut current_size = 0;
         loop {
@@ -95,27 +189,51 @@ impl<'a> DropDownloadPipeline<'a, Response, File> {
                 return Ok(false);
             }
 
-            let bytes_read = self.source.read(&mut copy_buf)?;
-            current_size += bytes_read;
-
-            buf_writer.write_all(&copy_buf[0..bytes_read])?;
-            self.progress.add(bytes_read);
-
-            if current_size == self.size {
+            if !self.source.next_piece(&mut copy_buf).await? {
                 break;
             }
+            let decoded = self.decoder.decode(&copy_buf)?;
+            current_size += decoded.len();
+            // Refuse to write past the chunk, it would land in the next one
+            if current_size > self.size {
+                return Err(chunk_too_long(self.size));
+            }
+
+            self.destination.write_all(decoded).await?;
+            self.progress.add(decoded.len());
+            if self.source.is_remote() {
+                self.progress.add_transfer(copy_buf.len(), decoded.len());
+            }
+        }
+
+        let decoded = self.decoder.finish()?;
+        current_size += decoded.len();
+        if current_size > self.size {
+            return Err(chunk_too_long(self.size));
+        }
+        self.destination.write_all(decoded).await?;
+        self.progress.add(decoded.len());
+        if self.source.is_remote() {
+            self.progress.add_transfer(0, decoded.len());
+        }
+
+        if current_size < self.size {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("source ended after {} of {} bytes", current_size, self.size),
+            ));
         }
 
         Ok(true)
     }
 
-    fn finish(self) -> Result<Digest, io::Error> {
-        let checksum = self.destination.finish()?;
+    async fn finish(self) -> Result<Digest, io::Error> {
+        let checksum = self.destination.finish().await?;
         Ok(checksum)
     }
 }
 
-pub fn download_game_chunk(
+pub async fn download_game_chunk(
     ctx: &DropDownloadContext,
     control_flag: &DownloadThreadControl,
     progress: ProgressHandle,
@@ -127,43 +245,103 @@ pub fn download_game_chunk(
         return Ok(false);
     }
 
-    let response = request
-        .send()
-        .map_err(|e| ApplicationDownloadError::Communication(e.into()))?;
+    let response = send_async_request(request)
+        .await
+        .map_err(ApplicationDownloadError::Communication)?;
 
-    if response.status() != 200 {
-        let err = response.json().unwrap();
-        return Err(ApplicationDownloadError::Communication(
-            RemoteAccessError::InvalidResponse(err),
-        ));
-    }
+    let encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .map(|encoding| encoding.to_str().unwrap_or_default().to_string());
+    let decoder = ChunkDecoder::for_encoding(encoding.as_deref()).map_err(|e| {
+        ApplicationDownloadError::Communication(RemoteAccessError::Generic(e.to_string()))
+    })?;
 
-    let mut destination = DropWriter::new(ctx.path.clone());
+    // Checked against the manifest rather than the Content-Length,
+    // which is the compressed size when there's an encoding
+    write_game_chunk(
+        ctx,
+        control_flag,
+        progress,
+        ChunkSource::Remote(response),
+        decoder,
+    )
+    .await
+}
 
-    if ctx.offset != 0 {
-        destination
-            .seek(SeekFrom::Start(ctx.offset))
-            .expect("Failed to seek to file offset");
-    }
+/// Path of a chunk's source file inside a depot directory.
+///
+/// Depots are laid out like the server's library:
+/// `<depot>/<game id>/<version name>/<file name>`
+pub fn depot_source_path(depot: &Path, ctx: &DropDownloadContext) -> PathBuf {
+    depot
+        .join(&ctx.game_id)
+        .join(&ctx.version)
+        .join(&ctx.file_name)
+}
+
+fn chunk_too_long(size: usize) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("chunk is longer than the expected {} bytes", size),
+    )
+}
 
-    let content_length = response.content_length();
-    if content_length.is_none() {
-        warn!("recieved 0 length content from server");
-        return Err(ApplicationDownloadError::Communication(
-            RemoteAccessError::InvalidResponse(response.json().unwrap()),
-        ));
+/// Copies a chunk out of a local (or network mounted) depot directory
+/// rather than fetching it from the server
+pub async fn copy_game_chunk(
+    ctx: &DropDownloadContext,
+    depot: &Path,
+    control_flag: &DownloadThreadControl,
+    progress: ProgressHandle,
+) -> Result<bool, ApplicationDownloadError> {
+    // If we're paused
+    if control_flag.get() == DownloadThreadControlFlag::Stop {
+        progress.set(0);
+        return Ok(false);
     }
 
+    let mut source = File::open(depot_source_path(depot, ctx))
+        .await
+        .map_err(|e| ApplicationDownloadError::IoError(e.kind()))?;
+    source
+        .seek(SeekFrom::Start(ctx.offset))
+        .await
+        .map_err(|e| ApplicationDownloadError::IoError(e.kind()))?;
+
+    write_game_chunk(
+        ctx,
+        control_flag,
+        progress,
+        ChunkSource::Depot(source.take(ctx.length as u64)),
+        ChunkDecoder::Identity,
+    )
+    .await
+}
+
+async fn write_game_chunk(
+    ctx: &DropDownloadContext,
+    control_flag: &DownloadThreadControl,
+    progress: ProgressHandle,
+    source: ChunkSource,
+    decoder: ChunkDecoder,
+) -> Result<bool, ApplicationDownloadError> {
+    let destination = DropWriter::open(&ctx.path, ctx.offset)
+        .await
+        .map_err(|e| ApplicationDownloadError::IoError(e.kind()))?;
+
     let mut pipeline = DropDownloadPipeline::new(
-        response,
+        source,
+        decoder,
         destination,
         control_flag,
         progress,
-        content_length.unwrap().try_into().unwrap(),
+        ctx.length,
     );
 
     let completed = pipeline
         .copy()
+        .await
         .map_err(|e| ApplicationDownloadError::IoError(e.kind()))?;
     if !completed {
         return Ok(false);
@@ -173,11 +351,14 @@ pub fn download_game_chunk(
     #[cfg(unix)]
     {
         let permissions = Permissions::from_mode(ctx.permissions);
-        set_permissions(ctx.path.clone(), permissions).unwrap();
+        tokio::fs::set_permissions(&ctx.path, permissions)
+            .await
+            .map_err(|e| ApplicationDownloadError::IoError(e.kind()))?;
     }
 
     let checksum = pipeline
         .finish()
+        .await
         .map_err(|e| ApplicationDownloadError::IoError(e.kind()))?;
 
     let res = hex::encode(checksum.0);
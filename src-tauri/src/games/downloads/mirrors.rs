@@ -0,0 +1,134 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{info, warn};
+use url::Url;
+
+use crate::download_manager::progress_object::{DownloadSourceStats, ProgressObject};
+
+/// Failures in a row before a mirror stops being handed out
+const MAX_MIRROR_FAILURES: usize = 3;
+/// The primary server is always the first entry, and is never marked down
+pub const PRIMARY_MIRROR: usize = 0;
+
+struct Mirror {
+    base_url: Url,
+    failures: AtomicUsize,
+    down: AtomicBool,
+    bytes: AtomicUsize,
+    millis: AtomicUsize,
+}
+
+impl Mirror {
+    fn new(base_url: Url) -> Self {
+        Self {
+            base_url,
+            failures: AtomicUsize::new(0),
+            down: AtomicBool::new(false),
+            bytes: AtomicUsize::new(0),
+            millis: AtomicUsize::new(0),
+        }
+    }
+    fn is_healthy(&self) -> bool {
+        !self.down.load(Ordering::Relaxed)
+    }
+    // Bytes per millisecond, which is (roughly) kilobytes per second
+    fn speed(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed) / self.millis.load(Ordering::Relaxed).max(1)
+    }
+}
+
+/// Spreads chunk requests across the primary server and any mirrors
+/// the server has listed for this version
+///
+/// Mirrors are handed out round-robin. A mirror that fails
+/// MAX_MIRROR_FAILURES times in a row is marked down, and once every
+/// mirror is down everything falls back to the primary server.
+pub struct MirrorPool {
+    mirrors: Vec<Mirror>,
+    next: AtomicUsize,
+    progress: Arc<ProgressObject>,
+}
+
+impl MirrorPool {
+    pub fn new(primary: Url, mirrors: Vec<Url>, progress: Arc<ProgressObject>) -> Self {
+        let mirrors: Vec<Mirror> = [primary]
+            .into_iter()
+            .chain(mirrors)
+            .map(Mirror::new)
+            .collect();
+
+        let pool = Self {
+            mirrors,
+            next: AtomicUsize::new(0),
+            progress,
+        };
+        pool.publish_stats();
+        pool
+    }
+
+    /// Picks the next healthy mirror, returning its index and base URL
+    pub fn select(&self) -> (usize, Url) {
+        let len = self.mirrors.len();
+        for _ in 0..len {
+            let index = self.next.fetch_add(1, Ordering::Relaxed) % len;
+            let mirror = &self.mirrors[index];
+            if mirror.is_healthy() {
+                return (index, mirror.base_url.clone());
+            }
+        }
+        (
+            PRIMARY_MIRROR,
+            self.mirrors[PRIMARY_MIRROR].base_url.clone(),
+        )
+    }
+
+    pub fn record_success(&self, index: usize, bytes: usize, elapsed: Duration) {
+        let mirror = &self.mirrors[index];
+        mirror.failures.store(0, Ordering::Relaxed);
+        mirror.bytes.fetch_add(bytes, Ordering::Relaxed);
+        mirror
+            .millis
+            .fetch_add(elapsed.as_millis() as usize, Ordering::Relaxed);
+        self.publish_stats();
+    }
+
+    /// Returns whether the chunk should be retried on another mirror.
+    /// Failures on the primary server are never retried here
+    pub fn record_failure(&self, index: usize) -> bool {
+        if index == PRIMARY_MIRROR {
+            return false;
+        }
+
+        let mirror = &self.mirrors[index];
+        let failures = mirror.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+            "mirror {} failed ({}/{})",
+            mirror.base_url, failures, MAX_MIRROR_FAILURES
+        );
+        if failures >= MAX_MIRROR_FAILURES && !mirror.down.swap(true, Ordering::Relaxed) {
+            info!("marking mirror {} as down", mirror.base_url);
+        }
+        self.publish_stats();
+
+        true
+    }
+
+    fn publish_stats(&self) {
+        let stats = self
+            .mirrors
+            .iter()
+            .map(|mirror| DownloadSourceStats {
+                name: mirror.base_url.to_string(),
+                speed: mirror.speed(),
+                healthy: mirror.is_healthy(),
+            })
+            .collect();
+        self.progress.set_source_stats(stats);
+    }
+}
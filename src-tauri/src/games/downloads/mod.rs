@@ -2,4 +2,5 @@ pub mod commands;
 pub mod download_agent;
 mod download_logic;
 mod manifest;
-mod stored_manifest;
+mod mirrors;
+pub mod stored_manifest;
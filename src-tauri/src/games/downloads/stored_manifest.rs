@@ -1,7 +1,7 @@
 use std::{
     fs::File,
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Mutex,
 };
 
@@ -28,29 +28,42 @@ impl StoredManifest {
             completed_contexts: Mutex::new(Vec::new()),
         }
     }
+    /// Picks up the stored manifest if it is for the same version, as
+    /// completed chunks from any other version can't be reused
     pub fn generate(game_id: String, game_version: String, base_path: PathBuf) -> Self {
-        let mut file = match File::open(base_path.join(DROP_DATA_PATH)) {
-            Ok(file) => file,
-            Err(_) => return StoredManifest::new(game_id, game_version, base_path),
-        };
+        match StoredManifest::open(&base_path) {
+            Some(manifest)
+                if manifest.game_id == game_id && manifest.game_version == game_version =>
+            {
+                manifest
+            }
+            _ => StoredManifest::new(game_id, game_version, base_path),
+        }
+    }
+    /// Reads the manifest stored in a directory, if there is a valid one
+    pub fn open(base_path: &Path) -> Option<Self> {
+        let mut file = File::open(base_path.join(DROP_DATA_PATH)).ok()?;
 
         let mut s = Vec::new();
         match file.read_to_end(&mut s) {
             Ok(_) => {}
             Err(e) => {
                 error!("{}", e);
-                return StoredManifest::new(game_id, game_version, base_path);
+                return None;
             }
         };
 
         match serde_binary::from_vec::<StoredManifest>(s, Endian::Little) {
-            Ok(manifest) => manifest,
+            Ok(manifest) => Some(manifest),
             Err(e) => {
                 warn!("{}", e);
-                StoredManifest::new(game_id, game_version, base_path)
+                None
             }
         }
     }
+    pub fn path(base_path: &Path) -> PathBuf {
+        base_path.join(DROP_DATA_PATH)
+    }
     pub fn write(&self) {
         let manifest_raw = match serde_binary::to_vec(&self, Endian::Little) {
             Ok(json) => json,
@@ -70,6 +83,12 @@ impl StoredManifest {
             Err(e) => error!("{}", e),
         };
     }
+    pub fn game_id(&self) -> &String {
+        &self.game_id
+    }
+    pub fn game_version(&self) -> &String {
+        &self.game_version
+    }
     pub fn set_completed_contexts(&self, completed_contexts: &[usize]) {
         *self.completed_contexts.lock().unwrap() = completed_contexts.to_owned();
     }
@@ -1,22 +1,34 @@
-use std::fs::remove_dir_all;
+use std::fs::{read_dir, remove_dir_all, remove_file};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
-use std::thread::spawn;
+use std::time::{Duration, Instant};
 
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use tauri::Emitter;
 use tauri::{AppHandle, Manager};
 
-use crate::database::db::{borrow_db_checked, borrow_db_mut_checked, save_db, GameVersion};
+use crate::database::db::{
+    borrow_db_checked, borrow_db_mut_checked, save_db, set_game_status, Database,
+    DatabaseApplications, GameVersion,
+};
 use crate::database::db::{ApplicationTransientStatus, GameDownloadStatus};
+use crate::database::dir_stats::refresh_game_size;
 use crate::download_manager::download_manager::DownloadStatus;
-use crate::download_manager::downloadable_metadata::DownloadableMetadata;
+use crate::download_manager::downloadable_metadata::{DownloadType, DownloadableMetadata};
+use crate::download_manager::progress_object::DownloadSourceStats;
+use crate::error::database_error::DatabaseError;
+use crate::error::library_error::LibraryError;
 use crate::error::remote_access_error::RemoteAccessError;
+use crate::games::rollback::discard_rollback_snapshot;
 use crate::games::state::{GameStatusManager, GameStatusWithTransient};
-use crate::remote::auth::generate_authorization_header;
-use crate::remote::requests::make_request;
+use crate::remote::requests::{game_source, make_profile_request, send_request};
 use crate::AppState;
 
+// Side-by-side versions are installed to <install dir>/<game id>@<version>
+const SIDE_BY_SIDE_SEPARATOR: char = '@';
+
 #[derive(serde::Serialize)]
 pub struct FetchGameStruct {
     game: Game,
@@ -36,14 +48,16 @@ pub struct Game {
     m_banner_id: String,
     m_cover_id: String,
     m_image_library: Vec<String>,
+    // Filled in by the client, not the server
+    #[serde(default)]
+    profile: String,
+    #[serde(default)]
+    server_id: String,
 }
 #[derive(serde::Serialize, Clone)]
 pub struct GameUpdateEvent {
     pub game_id: String,
-    pub status: (
-        Option<GameDownloadStatus>,
-        Option<ApplicationTransientStatus>,
-    ),
+    pub status: GameStatusWithTransient,
 }
 
 #[derive(Serialize, Clone)]
@@ -60,35 +74,75 @@ pub struct QueueUpdateEvent {
     pub queue: Vec<QueueUpdateEventQueueData>,
 }
 
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InstalledVersion {
+    pub version_name: String,
+    pub install_dir: String,
+    pub setup_required: bool,
+    pub default: bool,
+}
+
 #[derive(serde::Serialize, Clone)]
+pub struct UninstallProgressEvent {
+    pub game_id: String,
+    pub removed: usize,
+    pub total: usize,
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct StatsUpdateEvent {
     pub speed: usize,
     pub time: usize,
+    pub threads: usize,
+    pub sources: Vec<DownloadSourceStats>,
+    // Both only count chunks fetched from a server
+    pub wire_bytes: usize,
+    pub disk_bytes: usize,
+    pub compression_ratio: f64,
 }
 
+/// Fetches the library of the active server profile, or of every signed
+/// in profile when the merged library is enabled
 pub fn fetch_library_logic(app: AppHandle) -> Result<Vec<Game>, RemoteAccessError> {
-    let header = generate_authorization_header();
-
-    let client = reqwest::blocking::Client::new();
-    let response = make_request(&client, &["/api/v1/client/user/library"], &[], |f| {
-        f.header("Authorization", header)
-    })?
-    .send()?;
-
-    if response.status() != 200 {
-        let err = response.json().unwrap();
-        warn!("{:?}", err);
-        return Err(RemoteAccessError::InvalidResponse(err));
-    }
+    let (active_profile, profiles) = {
+        let db_handle = borrow_db_checked()?;
+        let mut profiles = vec![db_handle.active_profile.clone()];
+        if db_handle.settings.merged_library {
+            let mut others: Vec<String> = db_handle
+                .profiles
+                .iter()
+                .filter(|(name, profile)| {
+                    **name != db_handle.active_profile && profile.auth.is_some()
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            others.sort();
+            profiles.extend(others);
+        }
+        (db_handle.active_profile.clone(), profiles)
+    };
 
-    let games: Vec<Game> = response.json()?;
+    let mut games = Vec::new();
+    for profile in profiles {
+        match fetch_profile_library(&profile) {
+            Ok(profile_games) => games.extend(profile_games),
+            // One unreachable server shouldn't hide the rest of a merged library
+            Err(e) if profile != active_profile => {
+                warn!("could not fetch library of profile {}: {}", profile, e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
 
     let state = app.state::<Mutex<AppState>>();
     let mut handle = state.lock().unwrap();
 
-    let mut db_handle = borrow_db_mut_checked();
+    let mut db_handle = borrow_db_mut_checked()?;
 
-    for game in games.iter() {
+    for game in games.iter_mut() {
+        game.id = db_handle.local_game_id(&game.profile, &game.server_id);
         handle.games.insert(game.id.clone(), game.clone());
         if !db_handle.applications.game_statuses.contains_key(&game.id) {
             db_handle
@@ -99,7 +153,26 @@ pub fn fetch_library_logic(app: AppHandle) -> Result<Vec<Game>, RemoteAccessErro
     }
 
     drop(handle);
+    drop(db_handle);
+    // Any newly assigned IDs have to survive a restart
+    save_db()?;
+
+    Ok(games)
+}
 
+fn fetch_profile_library(profile: &str) -> Result<Vec<Game>, RemoteAccessError> {
+    let response = send_request(make_profile_request(
+        profile,
+        &["/api/v1/client/user/library"],
+        &[],
+        |f| f,
+    )?)?;
+
+    let mut games: Vec<Game> = response.json()?;
+    for game in games.iter_mut() {
+        game.profile = profile.to_string();
+        game.server_id = game.id.clone();
+    }
     Ok(games)
 }
 
@@ -112,7 +185,7 @@ pub fn fetch_game_logic(
 
     let game = state_handle.games.get(&id);
     if let Some(game) = game {
-        let status = GameStatusManager::fetch_state(&id);
+        let status = GameStatusManager::fetch_state(&id)?;
 
         let data = FetchGameStruct {
             game: game.clone(),
@@ -121,34 +194,36 @@ pub fn fetch_game_logic(
 
         return Ok(data);
     }
-    let client = reqwest::blocking::Client::new();
-    let response = make_request(&client, &["/api/v1/game/", &id], &[], |r| {
-        r.header("Authorization", generate_authorization_header())
-    })?
-    .send()?;
-
-    if response.status() == 404 {
-        return Err(RemoteAccessError::GameNotFound);
-    }
-    if response.status() != 200 {
-        let err = response.json().unwrap();
-        warn!("{:?}", err);
-        return Err(RemoteAccessError::InvalidResponse(err));
-    }
+    let (profile, server_id) = game_source(&id)?;
+    let request = make_profile_request(&profile, &["/api/v1/game/", &server_id], &[], |r| r)?;
+    let response = match send_request(request) {
+        Ok(response) => response,
+        Err(RemoteAccessError::InvalidResponse(err)) if err.status_code == 404 => {
+            return Err(RemoteAccessError::GameNotFound)
+        }
+        Err(e) => return Err(e),
+    };
 
-    let game: Game = response.json()?;
+    let mut game: Game = response.json()?;
+    let mut db_handle = borrow_db_mut_checked()?;
+    // A game that isn't known yet comes from the active profile, and its
+    // ID (from the URL) is the one the server gave it
+    let local_id = db_handle.local_game_id(&profile, &server_id);
+    game.id = local_id;
+    game.profile = profile;
+    game.server_id = server_id;
     state_handle.games.insert(id.clone(), game.clone());
 
-    let mut db_handle = borrow_db_mut_checked();
-
     db_handle
         .applications
         .game_statuses
         .entry(id.clone())
         .or_insert(GameDownloadStatus::Remote {});
     drop(db_handle);
+    // Keeps the mapping from local_game_id, so the ID stays the same next time
+    save_db()?;
 
-    let status = GameStatusManager::fetch_state(&id);
+    let status = GameStatusManager::fetch_state(&id)?;
 
     let data = FetchGameStruct {
         game: game.clone(),
@@ -162,21 +237,22 @@ pub fn fetch_game_verion_options_logic(
     game_id: String,
     state: tauri::State<'_, Mutex<AppState>>,
 ) -> Result<Vec<GameVersion>, RemoteAccessError> {
-    let client = reqwest::blocking::Client::new();
+    fetch_valid_game_versions(&game_id, &state)
+}
 
-    let response = make_request(
-        &client,
+/// Fetches every version of a game the server has, leaving out any
+/// that can't run on this platform
+pub fn fetch_valid_game_versions(
+    game_id: &String,
+    state: &Mutex<AppState>,
+) -> Result<Vec<GameVersion>, RemoteAccessError> {
+    let (profile, server_id) = game_source(game_id)?;
+    let response = send_request(make_profile_request(
+        &profile,
         &["/api/v1/client/game/versions"],
-        &[("id", &game_id)],
-        |r| r.header("Authorization", generate_authorization_header()),
-    )?
-    .send()?;
-
-    if response.status() != 200 {
-        let err = response.json().unwrap();
-        warn!("{:?}", err);
-        return Err(RemoteAccessError::InvalidResponse(err));
-    }
+        &[("id", &server_id)],
+        |r| r,
+    )?)?;
 
     let data: Vec<GameVersion> = response.json()?;
 
@@ -192,81 +268,414 @@ pub fn fetch_game_verion_options_logic(
     Ok(data)
 }
 
-pub fn uninstall_game_logic(meta: DownloadableMetadata, app_handle: &AppHandle) {
-    println!("triggered uninstall for agent");
-    let mut db_handle = borrow_db_mut_checked();
+/// Checks whether a game's files can be modified right now, without starting anything
+pub fn can_modify_game(meta: &DownloadableMetadata) -> Result<(), LibraryError> {
+    let db_handle = borrow_db_checked()?;
+    match db_handle.applications.transient_statuses.get(meta) {
+        Some(ApplicationTransientStatus::Running {}) => {
+            Err(LibraryError::GameRunning(meta.id.clone()))
+        }
+        Some(_) => Err(LibraryError::GameBusy(meta.id.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Blocking. Removes the installed files for a game one at a time, so
+/// progress can be shown as a file count. Should only be called from the
+/// DownloadManager, which makes sure it doesn't run alongside a download
+/// of the same game.
+///
+/// If it fails after some files have already been removed, the game is
+/// no longer usable, so it is marked as Remote. Anything left on disk is
+/// picked up as orphaned data later on.
+pub fn uninstall_game_logic(
+    meta: &DownloadableMetadata,
+    app_handle: &AppHandle,
+) -> Result<(), LibraryError> {
+    debug!("triggered uninstall for {:?}", meta);
+    let mut db_handle = borrow_db_mut_checked()?;
+    if let Some(ApplicationTransientStatus::Running {}) =
+        db_handle.applications.transient_statuses.get(meta)
+    {
+        return Err(LibraryError::GameRunning(meta.id.clone()));
+    }
+
+    let is_default = db_handle.applications.installed_game_version.get(&meta.id) == Some(meta);
+    let previous_state =
+        get_installed_status(&db_handle.applications, &meta.id, meta.version.as_ref());
+    let install_dir = match previous_state.as_ref().and_then(|s| s.install_dir()) {
+        Some(install_dir) => install_dir.clone(),
+        None => {
+            warn!("uninstall job doesn't have an installed state");
+            return Err(LibraryError::MetaNotFound(meta.id.clone()));
+        }
+    };
+
     db_handle
         .applications
         .transient_statuses
-        .entry(meta.clone())
-        .and_modify(|v| *v = ApplicationTransientStatus::Uninstalling {});
+        .insert(meta.clone(), ApplicationTransientStatus::Uninstalling {});
+    drop(db_handle);
 
     push_game_update(
         app_handle,
         &meta.id,
-        (None, Some(ApplicationTransientStatus::Uninstalling {})),
+        (
+            None,
+            Some(ApplicationTransientStatus::Uninstalling {}),
+            None,
+        ),
     );
 
-    let previous_state = db_handle.applications.game_statuses.get(&meta.id).cloned();
-    if previous_state.is_none() {
-        warn!("uninstall job doesn't have previous state, failing silently");
-        return;
-    }
-    let previous_state = previous_state.unwrap();
-    if let Some((_, install_dir)) = match previous_state {
-        GameDownloadStatus::Installed {
-            version_name,
-            install_dir,
-        } => Some((version_name, install_dir)),
-        GameDownloadStatus::SetupRequired {
-            version_name,
-            install_dir,
-        } => Some((version_name, install_dir)),
-        _ => None,
-    } {
-        db_handle
-            .applications
-            .transient_statuses
-            .entry(meta.clone())
-            .and_modify(|v| *v = ApplicationTransientStatus::Uninstalling {});
-        drop(db_handle);
-
-        let app_handle = app_handle.clone();
-        spawn(move || match remove_dir_all(install_dir) {
-            Err(e) => {
-                error!("{}", e);
+    let install_dir = PathBuf::from(install_dir);
+    let mut files = Vec::new();
+    let mut removed = 0;
+    let result = if !install_dir.exists() {
+        warn!("install dir for {} is already gone", meta.id);
+        Ok(())
+    } else {
+        collect_files(&install_dir, &mut files).and_then(|_| {
+            let total = files.len();
+            let mut last_update = Instant::now();
+            for file in files.iter() {
+                remove_file(file)?;
+                removed += 1;
+
+                if last_update.elapsed() >= UNINSTALL_PROGRESS_INTERVAL || removed == total {
+                    last_update = Instant::now();
+                    push_uninstall_progress(app_handle, &meta.id, removed, total);
+                }
             }
-            Ok(_) => {
-                let mut db_handle = borrow_db_mut_checked();
-                db_handle.applications.transient_statuses.remove(&meta);
-                db_handle
-                    .applications
-                    .game_statuses
-                    .entry(meta.id.clone())
-                    .and_modify(|e| *e = GameDownloadStatus::Remote {});
-                drop(db_handle);
-                save_db();
-
-                debug!("uninstalled game id {}", &meta.id);
-
-                push_game_update(
-                    &app_handle,
-                    &meta.id,
-                    (Some(GameDownloadStatus::Remote {}), None),
-                );
+            // Only (now empty) directories are left
+            remove_dir_all(&install_dir)
+        })
+    };
+
+    match result {
+        Ok(_) => {
+            let saved = set_game_status(app_handle, meta.clone(), |db_handle, meta| {
+                db_handle.applications.transient_statuses.remove(meta);
+                forget_installed_version(db_handle, meta, is_default);
+            });
+            if is_default {
+                discard_rollback_snapshot(&meta.id);
+            }
+            debug!("uninstalled game id {}", &meta.id);
+            Ok(saved?)
+        }
+        Err(e) => {
+            error!("failed to uninstall {}: {}", meta.id, e);
+            // The uninstall error is the one worth reporting
+            let _ = set_game_status(app_handle, meta.clone(), |db_handle, meta| {
+                db_handle.applications.transient_statuses.remove(meta);
+                if removed > 0 {
+                    forget_installed_version(db_handle, meta, is_default);
+                }
+            });
+            Err(LibraryError::UninstallFailed(meta.id.clone(), e))
+        }
+    }
+}
+
+/// Drops an uninstalled version from the database. If it was the
+/// default, the newest side-by-side version takes its place
+pub fn forget_installed_version(
+    db_handle: &mut Database,
+    meta: &DownloadableMetadata,
+    was_default: bool,
+) {
+    let applications = &mut db_handle.applications;
+    if !was_default {
+        if let Some(versions) = applications.side_by_side_installs.get_mut(&meta.id) {
+            versions.remove(&meta.version.clone().unwrap_or_default());
+            if versions.is_empty() {
+                applications.side_by_side_installs.remove(&meta.id);
             }
-        });
+        }
+        return;
     }
+
+    applications.installed_game_version.remove(&meta.id);
+    applications.game_sizes.remove(&meta.id);
+    applications.available_updates.remove(&meta.id);
+    applications.rolled_back_versions.remove(&meta.id);
+    applications
+        .game_statuses
+        .insert(meta.id.clone(), GameDownloadStatus::Remote {});
+
+    let mut versions = match applications.side_by_side_installs.remove(&meta.id) {
+        Some(versions) => versions,
+        None => return,
+    };
+    let newest = versions
+        .keys()
+        .max_by_key(|version_name| {
+            applications
+                .game_versions
+                .get(&meta.id)
+                .and_then(|game_versions| game_versions.get(*version_name))
+                .map(|game_version| game_version.version_index)
+        })
+        .cloned();
+    let (version_name, status) = match newest.and_then(|v| versions.remove_entry(&v)) {
+        Some(newest) => newest,
+        None => return,
+    };
+    if !versions.is_empty() {
+        applications
+            .side_by_side_installs
+            .insert(meta.id.clone(), versions);
+    }
+
+    info!("{} {} is now the default version", meta.id, version_name);
+    applications.installed_game_version.insert(
+        meta.id.clone(),
+        DownloadableMetadata::new(meta.id.clone(), Some(version_name), DownloadType::Game),
+    );
+    applications.game_statuses.insert(meta.id.clone(), status);
+}
+
+const UNINSTALL_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// Doesn't follow symlinks, so we never delete anything outside the install dir
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
 }
 
-pub fn get_current_meta(game_id: &String) -> Option<DownloadableMetadata> {
-    borrow_db_checked()
+fn push_uninstall_progress(app_handle: &AppHandle, game_id: &String, removed: usize, total: usize) {
+    app_handle
+        .emit(
+            &format!("update_uninstall/{}", game_id),
+            UninstallProgressEvent {
+                game_id: game_id.clone(),
+                removed,
+                total,
+            },
+        )
+        .unwrap();
+}
+
+pub fn get_current_meta(game_id: &String) -> Result<Option<DownloadableMetadata>, DatabaseError> {
+    Ok(borrow_db_checked()?
         .applications
         .installed_game_version
         .get(game_id)
+        .cloned())
+}
+
+/// Like get_current_meta, but for a specific installed version. Without
+/// a version, this is the default one
+pub fn get_installed_meta(
+    game_id: &String,
+    version: Option<String>,
+) -> Result<Option<DownloadableMetadata>, DatabaseError> {
+    let version = match version {
+        Some(version) => version,
+        None => return get_current_meta(game_id),
+    };
+    let db_handle = borrow_db_checked()?;
+    if get_installed_status(&db_handle.applications, game_id, Some(&version)).is_none() {
+        return Ok(None);
+    }
+    Ok(Some(DownloadableMetadata::new(
+        game_id.clone(),
+        Some(version),
+        DownloadType::Game,
+    )))
+}
+
+/// The status of an installed version of a game, or of the
+/// default version if no version is given
+pub fn get_installed_status(
+    applications: &DatabaseApplications,
+    game_id: &String,
+    version: Option<&String>,
+) -> Option<GameDownloadStatus> {
+    let default = applications
+        .game_statuses
+        .get(game_id)
+        .filter(|status| status.version_name().is_some());
+    let version = match version {
+        Some(version) => version,
+        None => return default.cloned(),
+    };
+    if default.is_some_and(|status| status.version_name() == Some(version)) {
+        return default.cloned();
+    }
+    applications
+        .side_by_side_installs
+        .get(game_id)
+        .and_then(|versions| versions.get(version))
         .cloned()
 }
 
+/// Replaces the status of an installed version, wherever it is kept
+pub fn replace_installed_status(
+    applications: &mut DatabaseApplications,
+    game_id: &String,
+    status: GameDownloadStatus,
+) {
+    let version_name = match status.version_name() {
+        Some(version_name) => version_name.clone(),
+        None => return,
+    };
+    let is_default = applications
+        .game_statuses
+        .get(game_id)
+        .is_some_and(|default| default.version_name() == Some(&version_name));
+    if is_default {
+        applications.game_statuses.insert(game_id.clone(), status);
+    } else if let Some(versions) = applications.side_by_side_installs.get_mut(game_id) {
+        versions.insert(version_name, status);
+    }
+}
+
+/// Every installed version of a game, starting with the default
+pub fn installed_statuses<'a>(
+    applications: &'a DatabaseApplications,
+    game_id: &String,
+) -> impl Iterator<Item = &'a GameDownloadStatus> + 'a {
+    applications
+        .game_statuses
+        .get(game_id)
+        .into_iter()
+        .chain(
+            applications
+                .side_by_side_installs
+                .get(game_id)
+                .into_iter()
+                .flat_map(|versions| versions.values()),
+        )
+        .filter(|status| status.version_name().is_some())
+}
+
+/// Where a version of a game goes when it's installed
+/// alongside the default one
+pub fn side_by_side_dir(base_dir: &Path, game_id: &String, version_name: &String) -> PathBuf {
+    // Version names are free text, so keep them from escaping the install dir
+    let version_name: String = version_name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    base_dir.join(format!(
+        "{}{}{}",
+        game_id, SIDE_BY_SIDE_SEPARATOR, version_name
+    ))
+}
+
+/// The ID of the game an install directory belongs to, going by its name
+pub fn install_dir_game_id(dir_name: &str) -> &str {
+    dir_name
+        .split(SIDE_BY_SIDE_SEPARATOR)
+        .next()
+        .unwrap_or(dir_name)
+}
+
+pub fn fetch_installed_versions_logic(
+    game_id: &String,
+) -> Result<Vec<InstalledVersion>, DatabaseError> {
+    let db_handle = borrow_db_checked()?;
+    let default_version = get_installed_status(&db_handle.applications, game_id, None)
+        .and_then(|status| status.version_name().cloned());
+    Ok(installed_statuses(&db_handle.applications, game_id)
+        .filter_map(|status| {
+            Some(InstalledVersion {
+                version_name: status.version_name()?.clone(),
+                install_dir: status.install_dir()?.clone(),
+                setup_required: matches!(status, GameDownloadStatus::SetupRequired { .. }),
+                default: status.version_name() == default_version.as_ref(),
+            })
+        })
+        .collect())
+}
+
+/// Makes an installed side-by-side version the one that is launched,
+/// updated and shown by default. No files are touched, so this only
+/// needs to wait for things that might be using the current default
+pub fn set_default_version_logic(
+    game_id: &String,
+    version_name: String,
+    app_handle: &AppHandle,
+) -> Result<(), LibraryError> {
+    let mut db_handle = borrow_db_mut_checked()?;
+    if db_handle
+        .applications
+        .transient_statuses
+        .keys()
+        .any(|meta| meta.id == *game_id)
+    {
+        return Err(LibraryError::GameBusy(game_id.clone()));
+    }
+    let current = get_installed_status(&db_handle.applications, game_id, None)
+        .ok_or_else(|| LibraryError::MetaNotFound(game_id.clone()))?;
+    let current_version = current.version_name().cloned().unwrap_or_default();
+    if current_version == version_name {
+        return Ok(());
+    }
+
+    let applications = &mut db_handle.applications;
+    let versions = applications
+        .side_by_side_installs
+        .get_mut(game_id)
+        .filter(|versions| versions.contains_key(&version_name))
+        .ok_or_else(|| LibraryError::VersionNotInstalled(game_id.clone(), version_name.clone()))?;
+    let new_default = versions.remove(&version_name).unwrap();
+    versions.insert(current_version, current);
+
+    applications
+        .game_statuses
+        .insert(game_id.clone(), new_default);
+    applications.installed_game_version.insert(
+        game_id.clone(),
+        DownloadableMetadata::new(game_id.clone(), Some(version_name), DownloadType::Game),
+    );
+    applications.available_updates.remove(game_id);
+    applications.game_sizes.remove(game_id);
+    drop(db_handle);
+    let saved = save_db();
+
+    // The snapshot is of whatever the old default was updated from,
+    // and restoring it would overwrite the new default's files
+    discard_rollback_snapshot(game_id);
+
+    push_game_update(
+        app_handle,
+        game_id,
+        GameStatusManager::fetch_state(game_id)?,
+    );
+    Ok(saved?)
+}
+
+/// Fetches what's needed to launch a version of a game
+pub fn fetch_game_version(
+    game_id: &String,
+    version_name: &String,
+) -> Result<GameVersion, RemoteAccessError> {
+    let (profile, server_id) = game_source(game_id)?;
+    let response = send_request(make_profile_request(
+        &profile,
+        &["/api/v1/client/metadata/version"],
+        &[("id", &server_id), ("version", version_name)],
+        |f| f,
+    )?)?;
+
+    Ok(response.json()?)
+}
+
 pub fn on_game_complete(
     meta: &DownloadableMetadata,
     install_dir: String,
@@ -277,36 +686,36 @@ pub fn on_game_complete(
         return Err(RemoteAccessError::GameNotFound);
     }
 
-    let header = generate_authorization_header();
-
-    let client = reqwest::blocking::Client::new();
-    let response = make_request(
-        &client,
-        &["/api/v1/client/metadata/version"],
-        &[
-            ("id", &meta.id),
-            ("version", meta.version.as_ref().unwrap()),
-        ],
-        |f| f.header("Authorization", header),
-    )?
-    .send()?;
-
-    let data: GameVersion = response.json()?;
+    let data = fetch_game_version(&meta.id, meta.version.as_ref().unwrap())?;
 
-    let mut handle = borrow_db_mut_checked();
+    let mut handle = borrow_db_mut_checked()?;
     handle
         .applications
         .game_versions
         .entry(meta.id.clone())
         .or_default()
         .insert(meta.version.clone().unwrap(), data.clone());
-    handle
-        .applications
-        .installed_game_version
-        .insert(meta.id.clone(), meta.clone());
+    // Anything installed somewhere other than where the default
+    // version lives was installed side-by-side
+    let is_default = match get_installed_status(&handle.applications, &meta.id, None) {
+        Some(default) => default.install_dir() == Some(&install_dir),
+        None => true,
+    };
+    if is_default {
+        handle
+            .applications
+            .installed_game_version
+            .insert(meta.id.clone(), meta.clone());
+        handle.applications.available_updates.remove(&meta.id);
+        handle.applications.rolled_back_versions.remove(&meta.id);
+    }
 
+    // Saved along with the status below
     drop(handle);
-    save_db();
+
+    if is_default {
+        refresh_game_size(&meta.id, Path::new(&install_dir));
+    }
 
     let status = if data.setup_command.is_empty() {
         GameDownloadStatus::Installed {
@@ -320,24 +729,36 @@ pub fn on_game_complete(
         }
     };
 
-    let mut db_handle = borrow_db_mut_checked();
+    if !is_default {
+        set_game_status(app_handle, meta.clone(), |db_handle, meta| {
+            db_handle
+                .applications
+                .side_by_side_installs
+                .entry(meta.id.clone())
+                .or_default()
+                .insert(meta.version.clone().unwrap(), status);
+        })?;
+        return Ok(());
+    }
+
+    let mut db_handle = borrow_db_mut_checked()?;
     db_handle
         .applications
         .game_statuses
         .insert(meta.id.clone(), status.clone());
     drop(db_handle);
-    save_db();
+    let saved = save_db();
     app_handle
         .emit(
             &format!("update_game/{}", meta.id),
             GameUpdateEvent {
                 game_id: meta.id.clone(),
-                status: (Some(status), None),
+                status: (Some(status), None, None),
             },
         )
         .unwrap();
 
-    Ok(())
+    Ok(saved?)
 }
 
 pub fn push_game_update(app_handle: &AppHandle, game_id: &String, status: GameStatusWithTransient) {
@@ -1,4 +1,8 @@
 pub mod commands;
 pub mod downloads;
 pub mod library;
+pub mod orphans;
+pub mod reconcile;
+pub mod relocation;
+pub mod rollback;
 pub mod state;
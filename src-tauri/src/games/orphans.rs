@@ -0,0 +1,229 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs::{read_dir, remove_dir_all, remove_file},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    database::{db::borrow_db_checked, dir_stats::dir_size},
+    download_manager::{download_manager::DownloadManager, downloadable::Downloadable},
+    error::{database_error::DatabaseError, library_error::LibraryError},
+    games::{
+        downloads::{download_agent::GameDownloadAgent, stored_manifest::StoredManifest},
+        library::{install_dir_game_id, installed_statuses},
+        rollback::is_rollback_dir,
+    },
+};
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub enum OrphanKind {
+    /// A directory nothing in the database points to
+    Untracked,
+    /// An untracked directory with a download manifest, left behind by a
+    /// failed or cancelled install. Queueing the same game and version
+    /// to the same install dir picks up where it left off
+    PartialInstall,
+    /// A manifest inside an installed game that belongs to a different
+    /// game or version than the one installed there
+    StaleManifest,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanedData {
+    pub path: PathBuf,
+    pub kind: OrphanKind,
+    pub size: u64,
+    /// Index into the install dirs this was found in
+    pub install_dir: usize,
+    pub game_id: Option<String>,
+    pub version: Option<String>,
+}
+
+/// Looks through every install directory for data the database doesn't
+/// know about. Games in `busy_game_ids` (queued or otherwise in use) are
+/// skipped, as their directories are expected to be incomplete.
+pub fn scan_orphaned_data(
+    busy_game_ids: &HashSet<String>,
+) -> Result<Vec<OrphanedData>, DatabaseError> {
+    let db_handle = borrow_db_checked()?;
+    let install_dirs = db_handle.applications.install_dirs.clone();
+    // Install dir => (game ID, installed version), for every installed version
+    let tracked: Vec<(PathBuf, String, String)> = db_handle
+        .applications
+        .game_statuses
+        .keys()
+        .flat_map(|game_id| {
+            installed_statuses(&db_handle.applications, game_id).filter_map(move |status| {
+                Some((
+                    PathBuf::from(status.install_dir()?),
+                    game_id.clone(),
+                    status.version_name()?.clone(),
+                ))
+            })
+        })
+        .collect();
+    let mut busy_game_ids = busy_game_ids.clone();
+    busy_game_ids.extend(
+        db_handle
+            .applications
+            .transient_statuses
+            .keys()
+            .map(|meta| meta.id.clone()),
+    );
+    drop(db_handle);
+
+    let mut orphans = Vec::new();
+    for (install_dir_index, install_dir) in install_dirs.iter().enumerate() {
+        let entries = match read_dir(install_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("could not scan {}: {}", install_dir.display(), e);
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !entry.file_type().is_ok_and(|file_type| file_type.is_dir())
+                || is_rollback_dir(&path)
+            {
+                continue;
+            }
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+            if busy_game_ids.contains(install_dir_game_id(&dir_name)) {
+                continue;
+            }
+
+            match tracked
+                .iter()
+                .find(|(tracked_dir, _, _)| *tracked_dir == path)
+            {
+                Some((_, game_id, version)) => {
+                    if let Some(orphan) =
+                        check_stale_manifest(&path, install_dir_index, game_id, version)
+                    {
+                        orphans.push(orphan);
+                    }
+                }
+                None => {
+                    let manifest = StoredManifest::open(&path);
+                    orphans.push(OrphanedData {
+                        kind: if manifest.is_some() {
+                            OrphanKind::PartialInstall
+                        } else {
+                            OrphanKind::Untracked
+                        },
+                        size: dir_size(&path).unwrap_or_else(|e| {
+                            warn!("could not measure {}: {}", path.display(), e);
+                            0
+                        }),
+                        install_dir: install_dir_index,
+                        game_id: manifest.as_ref().map(|m| m.game_id().clone()),
+                        version: manifest.as_ref().map(|m| m.game_version().clone()),
+                        path,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(orphans)
+}
+
+fn check_stale_manifest(
+    game_dir: &Path,
+    install_dir_index: usize,
+    game_id: &String,
+    version: &String,
+) -> Option<OrphanedData> {
+    let manifest = StoredManifest::open(game_dir)?;
+    if manifest.game_id() == game_id && manifest.game_version() == version {
+        return None;
+    }
+
+    let path = StoredManifest::path(game_dir);
+    Some(OrphanedData {
+        size: path.metadata().map(|m| m.len()).unwrap_or(0),
+        path,
+        kind: OrphanKind::StaleManifest,
+        install_dir: install_dir_index,
+        game_id: Some(manifest.game_id().clone()),
+        version: Some(manifest.game_version().clone()),
+    })
+}
+
+/// Deletes something previously reported by `scan_orphaned_data`.
+/// The scan is run again first, so only paths that are still orphaned
+/// can ever be removed
+pub fn delete_orphaned_data(
+    path: PathBuf,
+    busy_game_ids: &HashSet<String>,
+) -> Result<u64, LibraryError> {
+    let orphan = scan_orphaned_data(busy_game_ids)?
+        .into_iter()
+        .find(|orphan| orphan.path == path)
+        .ok_or_else(|| LibraryError::OrphanNotFound(path.clone()))?;
+
+    info!("deleting orphaned data at {}", orphan.path.display());
+    let result: io::Result<()> = match orphan.kind {
+        OrphanKind::StaleManifest => remove_file(&orphan.path),
+        OrphanKind::Untracked | OrphanKind::PartialInstall => remove_dir_all(&orphan.path),
+    };
+    result.map_err(|e| LibraryError::CleanupFailed(orphan.path.clone(), e))?;
+
+    Ok(orphan.size)
+}
+
+/// Queues the game and version a partial install's manifest belongs to
+/// into the directory it was found in. Chunks already on disk are kept,
+/// so a complete install is just registered as installed again
+pub fn adopt_orphaned_data(
+    path: PathBuf,
+    busy_game_ids: &HashSet<String>,
+    download_manager: &DownloadManager,
+) -> Result<(), LibraryError> {
+    let orphan = scan_orphaned_data(busy_game_ids)?
+        .into_iter()
+        .find(|orphan| orphan.path == path && orphan.kind == OrphanKind::PartialInstall)
+        .ok_or_else(|| LibraryError::OrphanNotFound(path.clone()))?;
+    let (Some(game_id), Some(version)) = (orphan.game_id, orphan.version) else {
+        return Err(LibraryError::OrphanNotFound(path));
+    };
+
+    // Side-by-side installs are named after their version as well
+    let side_by_side = orphan
+        .path
+        .file_name()
+        .is_some_and(|name| name != OsStr::new(&game_id));
+    let game_download_agent = GameDownloadAgent::new(
+        game_id.clone(),
+        version.clone(),
+        orphan.install_dir,
+        side_by_side,
+        download_manager.get_sender(),
+    )?;
+    // The agent picks its directory from what's installed, and it has to
+    // land on this one for the chunks to be reused
+    if game_download_agent.stored_manifest.base_path != orphan.path {
+        return Err(LibraryError::OrphanNotAdoptable(orphan.path));
+    }
+
+    info!(
+        "adopting {} {} from {}",
+        game_id,
+        version,
+        orphan.path.display()
+    );
+    download_manager
+        .queue_download(Arc::new(
+            Box::new(game_download_agent) as Box<dyn Downloadable + Send + Sync>
+        ))
+        .map_err(|_| LibraryError::QueueFailed(game_id))
+}
@@ -0,0 +1,264 @@
+use std::{collections::HashSet, fs::read_dir, path::Path};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::db::{borrow_db_checked, borrow_db_mut_checked, save_db, DatabaseApplications},
+    download_manager::downloadable_metadata::{DownloadType, DownloadableMetadata},
+    error::{database_error::DatabaseError, library_error::LibraryError},
+    games::{
+        downloads::stored_manifest::StoredManifest,
+        library::{fetch_game_version, forget_installed_version, installed_statuses},
+    },
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InconsistencyKind {
+    /// An installed version whose directory no longer exists
+    MissingInstallDir,
+    /// An installed version without the GameVersion needed to launch it
+    MissingVersionData,
+    /// An installed version whose directory is empty, or holds an
+    /// unfinished download of another version, usually an interrupted update
+    IncompleteInstall,
+    /// installed_game_version doesn't match the default installed version
+    InstalledVersionMismatch,
+    /// A transient status left behind by a download or process that isn't running
+    LeftoverTransientStatus,
+}
+impl InconsistencyKind {
+    /// Whether the fix is applied without asking. Forgetting an incomplete
+    /// install leaves the files in place, but the game stops being playable
+    fn automatic(&self) -> bool {
+        !matches!(self, InconsistencyKind::IncompleteInstall)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Inconsistency {
+    pub game_id: String,
+    pub version: Option<String>,
+    pub kind: InconsistencyKind,
+    /// Whether the fix was applied during the pass that found it
+    #[serde(default)]
+    pub fixed: bool,
+}
+
+/// Checks game_statuses, installed_game_version, game_versions and the
+/// transient statuses against each other and against what's on disk.
+/// Games in `busy_game_ids` (queued or running) are skipped, as they're
+/// expected to be in flux.
+pub fn find_inconsistencies(
+    busy_game_ids: &HashSet<String>,
+) -> Result<Vec<Inconsistency>, DatabaseError> {
+    let db_handle = borrow_db_checked()?;
+    let applications = &db_handle.applications;
+    let mut found = Vec::new();
+    let mut push = |game_id: &String, version: Option<&String>, kind| {
+        found.push(Inconsistency {
+            game_id: game_id.clone(),
+            version: version.cloned(),
+            kind,
+            fixed: false,
+        })
+    };
+
+    for meta in applications.transient_statuses.keys() {
+        if !busy_game_ids.contains(&meta.id) {
+            push(
+                &meta.id,
+                meta.version.as_ref(),
+                InconsistencyKind::LeftoverTransientStatus,
+            );
+        }
+    }
+
+    let game_ids: HashSet<&String> = applications
+        .game_statuses
+        .keys()
+        .chain(applications.installed_game_version.keys())
+        .collect();
+    for game_id in game_ids {
+        if busy_game_ids.contains(game_id) {
+            continue;
+        }
+
+        for status in installed_statuses(applications, game_id) {
+            let (Some(version), Some(install_dir)) = (status.version_name(), status.install_dir())
+            else {
+                continue;
+            };
+            let install_dir = Path::new(install_dir);
+            if !install_dir.is_dir() {
+                push(game_id, Some(version), InconsistencyKind::MissingInstallDir);
+                continue;
+            }
+            if is_incomplete(install_dir, game_id, version) {
+                push(game_id, Some(version), InconsistencyKind::IncompleteInstall);
+            }
+            if !applications
+                .game_versions
+                .get(game_id)
+                .is_some_and(|versions| versions.contains_key(version))
+            {
+                push(
+                    game_id,
+                    Some(version),
+                    InconsistencyKind::MissingVersionData,
+                );
+            }
+        }
+
+        let default = default_version(applications, game_id);
+        let recorded = applications
+            .installed_game_version
+            .get(game_id)
+            .and_then(|meta| meta.version.as_ref());
+        if default != recorded {
+            push(
+                game_id,
+                default,
+                InconsistencyKind::InstalledVersionMismatch,
+            );
+        }
+    }
+
+    Ok(found)
+}
+
+/// Finds inconsistencies and applies every fix that's safe to apply
+/// without asking. The rest are returned unfixed, for the user to confirm
+pub fn reconcile(busy_game_ids: &HashSet<String>) -> Result<Vec<Inconsistency>, DatabaseError> {
+    let mut found = find_inconsistencies(busy_game_ids)?;
+    for inconsistency in found.iter_mut() {
+        if !inconsistency.kind.automatic() {
+            warn!(
+                "{:?} for {} {:?} needs to be confirmed before it's fixed",
+                inconsistency.kind, inconsistency.game_id, inconsistency.version
+            );
+            continue;
+        }
+        match fix_inconsistency(inconsistency) {
+            Ok(()) => {
+                info!(
+                    "fixed {:?} for {} {:?}",
+                    inconsistency.kind, inconsistency.game_id, inconsistency.version
+                );
+                inconsistency.fixed = true;
+            }
+            Err(e) => warn!(
+                "could not fix {:?} for {}: {}",
+                inconsistency.kind, inconsistency.game_id, e
+            ),
+        }
+    }
+
+    if found.iter().any(|inconsistency| inconsistency.fixed) {
+        save_db()?;
+    }
+    Ok(found)
+}
+
+/// Applies the fix for something previously reported by `reconcile`. The
+/// check is run again first, so only a fix that's still needed is applied
+pub fn confirm_fix(
+    inconsistency: &Inconsistency,
+    busy_game_ids: &HashSet<String>,
+) -> Result<(), LibraryError> {
+    let inconsistency = find_inconsistencies(busy_game_ids)?
+        .into_iter()
+        .find(|found| {
+            found.game_id == inconsistency.game_id
+                && found.version == inconsistency.version
+                && found.kind == inconsistency.kind
+        })
+        .ok_or_else(|| LibraryError::InconsistencyNotFound(inconsistency.game_id.clone()))?;
+
+    fix_inconsistency(&inconsistency)?;
+    info!(
+        "fixed {:?} for {} {:?}",
+        inconsistency.kind, inconsistency.game_id, inconsistency.version
+    );
+    Ok(save_db()?)
+}
+
+// Doesn't save, so a whole pass can be saved at once
+fn fix_inconsistency(inconsistency: &Inconsistency) -> Result<(), LibraryError> {
+    let game_id = &inconsistency.game_id;
+    let meta = DownloadableMetadata::new(
+        game_id.clone(),
+        inconsistency.version.clone(),
+        DownloadType::Game,
+    );
+
+    match inconsistency.kind {
+        InconsistencyKind::MissingVersionData => {
+            // Fetched before taking the lock, as it's a request to the server
+            let version = meta.version.unwrap_or_default();
+            let game_version = fetch_game_version(game_id, &version)?;
+            borrow_db_mut_checked()?
+                .applications
+                .game_versions
+                .entry(game_id.clone())
+                .or_default()
+                .insert(version, game_version);
+        }
+        // Files of an incomplete install are left where they are, so
+        // installing the same version there again can resume them
+        InconsistencyKind::MissingInstallDir | InconsistencyKind::IncompleteInstall => {
+            let mut db_handle = borrow_db_mut_checked()?;
+            let is_default =
+                default_version(&db_handle.applications, game_id) == meta.version.as_ref();
+            forget_installed_version(&mut db_handle, &meta, is_default);
+        }
+        InconsistencyKind::InstalledVersionMismatch => {
+            let mut db_handle = borrow_db_mut_checked()?;
+            let applications = &mut db_handle.applications;
+            match default_version(applications, game_id).cloned() {
+                Some(version) => applications.installed_game_version.insert(
+                    game_id.clone(),
+                    DownloadableMetadata::new(game_id.clone(), Some(version), DownloadType::Game),
+                ),
+                None => applications.installed_game_version.remove(game_id),
+            };
+        }
+        InconsistencyKind::LeftoverTransientStatus => {
+            borrow_db_mut_checked()?
+                .applications
+                .transient_statuses
+                .remove(&meta);
+        }
+    }
+    Ok(())
+}
+
+fn default_version<'a>(
+    applications: &'a DatabaseApplications,
+    game_id: &String,
+) -> Option<&'a String> {
+    applications
+        .game_statuses
+        .get(game_id)
+        .and_then(|status| status.version_name())
+}
+
+fn is_incomplete(install_dir: &Path, game_id: &String, version: &String) -> bool {
+    if let Some(manifest) = StoredManifest::open(install_dir) {
+        if manifest.game_id() != game_id || manifest.game_version() != version {
+            return true;
+        }
+    }
+
+    let manifest_path = StoredManifest::path(install_dir);
+    match read_dir(install_dir) {
+        Ok(mut entries) => {
+            !entries.any(|entry| entry.is_ok_and(|entry| entry.path() != manifest_path))
+        }
+        Err(e) => {
+            warn!("could not check {}: {}", install_dir.display(), e);
+            false
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    fs::{create_dir_all, read_dir, read_link, remove_dir_all, File},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use md5::Context;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::{
+    database::db::{
+        borrow_db_mut_checked, set_game_status, ApplicationTransientStatus, GameDownloadStatus,
+    },
+    download_manager::downloadable_metadata::DownloadableMetadata,
+    error::library_error::LibraryError,
+    games::{downloads::stored_manifest::StoredManifest, library::push_game_update},
+};
+
+const COPY_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Serialize, Clone)]
+pub struct MoveProgressEvent {
+    pub game_id: String,
+    pub current: u64,
+    pub max: u64,
+}
+
+/// Blocking. Copies an installed game into another install directory,
+/// verifies every file, points the database at the new copy and then
+/// removes the old one. Should only be called from the DownloadManager.
+///
+/// Until the database has been updated, the old copy is left untouched,
+/// so a failure only ever needs to clean up the half-written new copy.
+pub fn move_game_logic(
+    meta: &DownloadableMetadata,
+    target_base_dir: PathBuf,
+    app_handle: &AppHandle,
+) -> Result<(), LibraryError> {
+    let mut db_handle = borrow_db_mut_checked()?;
+    if db_handle.applications.transient_statuses.contains_key(meta) {
+        return Err(LibraryError::GameBusy(meta.id.clone()));
+    }
+
+    let previous_state = db_handle.applications.game_statuses.get(&meta.id).cloned();
+    let source_dir = match &previous_state {
+        Some(GameDownloadStatus::Installed { install_dir, .. })
+        | Some(GameDownloadStatus::SetupRequired { install_dir, .. }) => PathBuf::from(install_dir),
+        _ => return Err(LibraryError::MetaNotFound(meta.id.clone())),
+    };
+    let target_dir = target_base_dir.join(&meta.id);
+    if target_dir == source_dir {
+        return Err(LibraryError::MoveFailed(
+            meta.id.clone(),
+            io::Error::new(
+                ErrorKind::AlreadyExists,
+                "Game is already in this directory",
+            ),
+        ));
+    }
+    if target_dir.exists() && read_dir(&target_dir).map_or(true, |mut d| d.next().is_some()) {
+        return Err(LibraryError::MoveFailed(
+            meta.id.clone(),
+            io::Error::new(
+                ErrorKind::DirectoryNotEmpty,
+                "Target directory already contains files for this game",
+            ),
+        ));
+    }
+
+    db_handle
+        .applications
+        .transient_statuses
+        .insert(meta.clone(), ApplicationTransientStatus::Moving {});
+    drop(db_handle);
+    push_game_update(
+        app_handle,
+        &meta.id,
+        (None, Some(ApplicationTransientStatus::Moving {}), None),
+    );
+
+    info!(
+        "moving {} from {} to {}",
+        meta.id,
+        source_dir.display(),
+        target_dir.display()
+    );
+
+    let copy_result = copy_install_tree(&source_dir, &target_dir, |current, max| {
+        push_move_progress(app_handle, &meta.id, current, max)
+    });
+    if let Err(e) = copy_result {
+        error!("failed to move {}: {}", meta.id, e);
+        if let Err(e) = remove_dir_all(&target_dir) {
+            warn!(
+                "could not clean up partial copy at {}: {}",
+                target_dir.display(),
+                e
+            );
+        }
+        let _ = set_game_status(app_handle, meta.clone(), |db_handle, meta| {
+            db_handle.applications.transient_statuses.remove(meta);
+        });
+        return Err(LibraryError::MoveFailed(meta.id.clone(), e));
+    }
+
+    // The copied .dropdata still points at the old location
+    let mut stored_manifest = StoredManifest::generate(
+        meta.id.clone(),
+        meta.version.clone().unwrap_or_default(),
+        target_dir.clone(),
+    );
+    stored_manifest.base_path = target_dir.clone();
+    stored_manifest.write();
+
+    let new_install_dir = target_dir.to_string_lossy().to_string();
+    set_game_status(app_handle, meta.clone(), |db_handle, meta| {
+        db_handle.applications.transient_statuses.remove(meta);
+        if let Some(status) = db_handle.applications.game_statuses.get_mut(&meta.id) {
+            match status {
+                GameDownloadStatus::Installed { install_dir, .. }
+                | GameDownloadStatus::SetupRequired { install_dir, .. }
+                | GameDownloadStatus::PartiallyInstalled { install_dir, .. } => {
+                    *install_dir = new_install_dir;
+                }
+                GameDownloadStatus::Remote {} => {}
+            }
+        }
+    })?;
+
+    // The game is now fully usable from its new location, and the database
+    // on disk points there, so failing to remove the old copy only costs
+    // disk space
+    if let Err(e) = remove_dir_all(&source_dir) {
+        warn!(
+            "moved {} but could not remove the old copy at {}: {}",
+            meta.id,
+            source_dir.display(),
+            e
+        );
+    }
+
+    debug!("moved game id {}", meta.id);
+    Ok(())
+}
+
+/// Copies a whole install directory, checking every file against its
+/// source as it goes. Progress is reported in bytes, at most every
+/// COPY_PROGRESS_INTERVAL
+pub fn copy_install_tree<F: FnMut(u64, u64)>(
+    source_dir: &Path,
+    target_dir: &Path,
+    mut on_progress: F,
+) -> io::Result<()> {
+    let mut files = Vec::new();
+    collect_entries(source_dir, Path::new(""), &mut files)?;
+    let max = files
+        .iter()
+        .map(|file| source_dir.join(file).symlink_metadata().map(|m| m.len()))
+        .sum::<io::Result<u64>>()?;
+
+    let mut current = 0;
+    let mut last_update = Instant::now();
+    for relative in files {
+        let source = source_dir.join(&relative);
+        let target = target_dir.join(&relative);
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+
+        if source.symlink_metadata()?.file_type().is_symlink() {
+            copy_symlink(&source, &target)?;
+            continue;
+        }
+
+        let written_checksum = copy_file(&source, &target, |bytes| {
+            current += bytes as u64;
+            if last_update.elapsed() >= COPY_PROGRESS_INTERVAL {
+                last_update = Instant::now();
+                on_progress(current, max);
+            }
+        })?;
+        if hash_file(&target)? != written_checksum {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("checksum mismatch after copying {}", relative.display()),
+            ));
+        }
+    }
+    on_progress(max, max);
+
+    Ok(())
+}
+
+// Paths are collected relative to the root so they can be joined onto the target
+fn collect_entries(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in read_dir(root.join(relative))? {
+        let entry = entry?;
+        let entry_relative = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            collect_entries(root, &entry_relative, files)?;
+        } else {
+            files.push(entry_relative);
+        }
+    }
+    Ok(())
+}
+
+/// Copies a file, returning the md5 of what was read from the source
+fn copy_file<F: FnMut(usize)>(
+    source: &Path,
+    target: &Path,
+    mut on_progress: F,
+) -> io::Result<[u8; 16]> {
+    let source_file = File::open(source)?;
+    let permissions = source_file.metadata()?.permissions();
+    let mut reader = BufReader::with_capacity(COPY_BUFFER_SIZE, source_file);
+    let mut writer = BufWriter::with_capacity(COPY_BUFFER_SIZE, File::create(target)?);
+    let mut hasher = Context::new();
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+
+    loop {
+        let bytes_read = reader.read(&mut buf)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.consume(&buf[..bytes_read]);
+        writer.write_all(&buf[..bytes_read])?;
+        on_progress(bytes_read);
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+
+    std::fs::set_permissions(target, permissions)?;
+
+    Ok(hasher.compute().0)
+}
+
+fn hash_file(path: &Path) -> io::Result<[u8; 16]> {
+    let mut reader = BufReader::with_capacity(COPY_BUFFER_SIZE, File::open(path)?);
+    let mut hasher = Context::new();
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let bytes_read = reader.read(&mut buf)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.consume(&buf[..bytes_read]);
+    }
+    Ok(hasher.compute().0)
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(read_link(source)?, target)
+}
+
+#[cfg(windows)]
+fn copy_symlink(source: &Path, target: &Path) -> io::Result<()> {
+    // Windows needs to know up front whether the link is to a directory.
+    // Junctions report as symlinks too, and get recreated as directory links
+    let link = read_link(source)?;
+    let resolved = match source.parent() {
+        Some(parent) => parent.join(&link),
+        None => link.clone(),
+    };
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(link, target)
+    } else {
+        std::os::windows::fs::symlink_file(link, target)
+    }
+}
+
+fn push_move_progress(app_handle: &AppHandle, game_id: &String, current: u64, max: u64) {
+    app_handle
+        .emit(
+            &format!("update_move/{}", game_id),
+            MoveProgressEvent {
+                game_id: game_id.clone(),
+                current,
+                max,
+            },
+        )
+        .unwrap();
+}
@@ -0,0 +1,290 @@
+use std::{
+    fs::{remove_dir_all, rename},
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::{
+    database::db::{
+        borrow_db_checked, borrow_db_mut_checked, save_db, set_game_status,
+        ApplicationTransientStatus, GameDownloadStatus, RollbackSnapshot,
+    },
+    download_manager::downloadable_metadata::DownloadableMetadata,
+    error::library_error::LibraryError,
+    games::{
+        library::{can_modify_game, push_game_update},
+        relocation::copy_install_tree,
+    },
+};
+
+/// Snapshots live next to the games in each install dir, so restoring
+/// one is (usually) just a rename
+const ROLLBACK_DIR: &str = ".drop-rollback";
+
+#[derive(Serialize, Clone)]
+pub struct SnapshotProgressEvent {
+    pub game_id: String,
+    pub current: u64,
+    pub max: u64,
+}
+
+pub fn is_rollback_dir(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == ROLLBACK_DIR)
+}
+
+fn snapshot_path(install_dir: &Path, game_id: &String) -> PathBuf {
+    install_dir
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(ROLLBACK_DIR)
+        .join(game_id)
+}
+
+/// Blocking. Copies the installed version of a game aside before an
+/// update starts writing over it, if the user has asked for that.
+///
+/// Resuming an update doesn't take a new snapshot, as the installed
+/// version (and so the snapshot) hasn't changed yet. Progress is
+/// reported on update_snapshot/{game_id}
+pub fn ensure_rollback_snapshot(app_handle: &AppHandle, game_id: &String) -> io::Result<()> {
+    let db_lock = borrow_db_checked().map_err(io::Error::other)?;
+    if !db_lock.settings.keep_previous_version {
+        return Ok(());
+    }
+    let status = match db_lock.applications.game_statuses.get(game_id) {
+        Some(status) => status.clone(),
+        None => return Ok(()),
+    };
+    let (version_name, install_dir) = match &status {
+        GameDownloadStatus::Installed {
+            version_name,
+            install_dir,
+        }
+        | GameDownloadStatus::SetupRequired {
+            version_name,
+            install_dir,
+        } => (version_name.clone(), PathBuf::from(install_dir)),
+        // The snapshot was taken when the update first started
+        GameDownloadStatus::PartiallyInstalled { .. } | GameDownloadStatus::Remote {} => {
+            return Ok(())
+        }
+    };
+    if let Some(existing) = db_lock.applications.rollback_snapshots.get(game_id) {
+        if existing.version_name == version_name && existing.path.exists() {
+            debug!("already have a snapshot of {} {}", game_id, version_name);
+            return Ok(());
+        }
+    }
+    let game_version = db_lock
+        .applications
+        .game_versions
+        .get(game_id)
+        .and_then(|versions| versions.get(&version_name))
+        .cloned();
+    drop(db_lock);
+
+    // Only the one previous version is ever kept
+    discard_rollback_snapshot(game_id);
+
+    let path = snapshot_path(&install_dir, game_id);
+    info!(
+        "keeping {} {} at {} in case of rollback",
+        game_id,
+        version_name,
+        path.display()
+    );
+    let copied = copy_install_tree(&install_dir, &path, |current, max| {
+        push_snapshot_progress(app_handle, game_id, current, max)
+    });
+    if let Err(e) = copied {
+        let _ = remove_dir_all(&path);
+        return Err(e);
+    }
+
+    let mut db_lock = borrow_db_mut_checked().map_err(io::Error::other)?;
+    db_lock.applications.rollback_snapshots.insert(
+        game_id.clone(),
+        RollbackSnapshot {
+            version_name,
+            path,
+            status,
+            game_version,
+        },
+    );
+    drop(db_lock);
+    save_db().map_err(io::Error::other)
+}
+
+fn push_snapshot_progress(app_handle: &AppHandle, game_id: &String, current: u64, max: u64) {
+    app_handle
+        .emit(
+            &format!("update_snapshot/{}", game_id),
+            SnapshotProgressEvent {
+                game_id: game_id.clone(),
+                current,
+                max,
+            },
+        )
+        .unwrap();
+}
+
+/// Removes a game's snapshot from disk and the database, if it has one
+pub fn discard_rollback_snapshot(game_id: &String) {
+    let Ok(mut db_lock) = borrow_db_mut_checked() else {
+        return;
+    };
+    let snapshot = db_lock.applications.rollback_snapshots.remove(game_id);
+    drop(db_lock);
+
+    if let Some(snapshot) = snapshot {
+        let _ = save_db();
+        if let Err(e) = remove_dir_all(&snapshot.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!(
+                    "could not remove snapshot at {}: {}",
+                    snapshot.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Blocking. Swaps the installed files for the snapshot of the previous
+/// version. Should only be called from the DownloadManager.
+///
+/// The database is only touched once the files are in place, and then
+/// all in one go, so it never points at a half restored game.
+pub fn rollback_game_logic(
+    meta: &DownloadableMetadata,
+    app_handle: &AppHandle,
+) -> Result<(), LibraryError> {
+    can_modify_game(meta)?;
+
+    let mut db_handle = borrow_db_mut_checked()?;
+    let snapshot = db_handle
+        .applications
+        .rollback_snapshots
+        .get(&meta.id)
+        .cloned()
+        .ok_or_else(|| LibraryError::NoRollbackAvailable(meta.id.clone()))?;
+    let install_dir = match db_handle.applications.game_statuses.get(&meta.id) {
+        Some(GameDownloadStatus::Installed { install_dir, .. })
+        | Some(GameDownloadStatus::SetupRequired { install_dir, .. })
+        // Also the way back from an update that couldn't be finished
+        | Some(GameDownloadStatus::PartiallyInstalled { install_dir, .. }) => {
+            PathBuf::from(install_dir)
+        }
+        _ => return Err(LibraryError::MetaNotFound(meta.id.clone())),
+    };
+    if !snapshot.path.exists() {
+        drop(db_handle);
+        discard_rollback_snapshot(&meta.id);
+        return Err(LibraryError::NoRollbackAvailable(meta.id.clone()));
+    }
+    db_handle
+        .applications
+        .transient_statuses
+        .insert(meta.clone(), ApplicationTransientStatus::RollingBack {});
+    drop(db_handle);
+    push_game_update(
+        app_handle,
+        &meta.id,
+        (None, Some(ApplicationTransientStatus::RollingBack {}), None),
+    );
+
+    info!(
+        "rolling {} back to {} from {}",
+        meta.id,
+        snapshot.version_name,
+        snapshot.path.display()
+    );
+    if let Err(e) = swap_in_snapshot(&snapshot.path, &install_dir) {
+        let _ = set_game_status(app_handle, meta.clone(), |db_handle, meta| {
+            db_handle.applications.transient_statuses.remove(meta);
+        });
+        return Err(LibraryError::RollbackFailed(meta.id.clone(), e));
+    }
+
+    let restored_meta = DownloadableMetadata {
+        version: Some(snapshot.version_name.clone()),
+        ..meta.clone()
+    };
+    let install_dir = install_dir.to_string_lossy().to_string();
+    let restored_status = match snapshot.status {
+        GameDownloadStatus::SetupRequired { .. } => GameDownloadStatus::SetupRequired {
+            version_name: snapshot.version_name.clone(),
+            install_dir,
+        },
+        _ => GameDownloadStatus::Installed {
+            version_name: snapshot.version_name.clone(),
+            install_dir,
+        },
+    };
+    set_game_status(app_handle, meta.clone(), |db_handle, meta| {
+        let applications = &mut db_handle.applications;
+        applications.transient_statuses.remove(meta);
+        applications
+            .installed_game_version
+            .insert(meta.id.clone(), restored_meta);
+        applications
+            .game_statuses
+            .insert(meta.id.clone(), restored_status);
+        if let Some(game_version) = snapshot.game_version {
+            applications
+                .game_versions
+                .entry(meta.id.clone())
+                .or_default()
+                .insert(snapshot.version_name.clone(), game_version);
+        }
+        applications.rollback_snapshots.remove(&meta.id);
+        applications.available_updates.remove(&meta.id);
+        // Otherwise automatic updates would put it straight back
+        if let Some(rolled_back_from) = &meta.version {
+            applications
+                .rolled_back_versions
+                .insert(meta.id.clone(), rolled_back_from.clone());
+        }
+        applications.game_sizes.remove(&meta.id);
+    })?;
+
+    debug!("rolled back game id {}", meta.id);
+    Ok(())
+}
+
+/// Moves the current install out of the way, puts the snapshot in its
+/// place, and only then deletes the old files
+fn swap_in_snapshot(snapshot_dir: &Path, install_dir: &Path) -> io::Result<()> {
+    let replaced_dir = snapshot_dir.with_extension("replaced");
+    if replaced_dir.exists() {
+        remove_dir_all(&replaced_dir)?;
+    }
+    move_dir(install_dir, &replaced_dir)?;
+    if let Err(e) = move_dir(snapshot_dir, install_dir) {
+        // Put the current version back, so the game is left as it was
+        move_dir(&replaced_dir, install_dir)?;
+        return Err(e);
+    }
+
+    if let Err(e) = remove_dir_all(&replaced_dir) {
+        warn!(
+            "rolled back, but could not remove the replaced files at {}: {}",
+            replaced_dir.display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+// The snapshot is on another drive if the game was moved after it was taken
+fn move_dir(from: &Path, to: &Path) -> io::Result<()> {
+    if rename(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_install_tree(from, to, |_, _| {})?;
+    remove_dir_all(from)
+}
@@ -1,29 +1,47 @@
-use crate::database::db::{borrow_db_checked, ApplicationTransientStatus, GameDownloadStatus};
+use crate::{
+    database::db::{
+        borrow_db_checked, ApplicationTransientStatus, AvailableUpdate, GameDownloadStatus,
+    },
+    error::database_error::DatabaseError,
+};
 
+// The update is only ever set alongside an installed status
 pub type GameStatusWithTransient = (
     Option<GameDownloadStatus>,
     Option<ApplicationTransientStatus>,
+    Option<AvailableUpdate>,
 );
 pub struct GameStatusManager {}
 
 impl GameStatusManager {
-    pub fn fetch_state(game_id: &String) -> GameStatusWithTransient {
-        let db_lock = borrow_db_checked();
+    pub fn fetch_state(game_id: &String) -> Result<GameStatusWithTransient, DatabaseError> {
+        let db_lock = borrow_db_checked()?;
         let online_state = match db_lock.applications.installed_game_version.get(game_id) {
             Some(meta) => db_lock.applications.transient_statuses.get(meta).cloned(),
             None => None,
-        };
+        }
+        // A side-by-side version might be the one that's running
+        .or_else(|| {
+            db_lock
+                .applications
+                .transient_statuses
+                .iter()
+                .find(|(meta, status)| {
+                    meta.id == *game_id && matches!(status, ApplicationTransientStatus::Running {})
+                })
+                .map(|(_, status)| status.clone())
+        });
         let offline_state = db_lock.applications.game_statuses.get(game_id).cloned();
+        let update = db_lock.applications.available_updates.get(game_id).cloned();
         drop(db_lock);
 
         if online_state.is_some() {
-            return (None, online_state);
-        }
-
-        if offline_state.is_some() {
-            return (offline_state, None);
+            return Ok((None, online_state, None));
         }
 
-        (None, None)
+        Ok(match offline_state {
+            Some(GameDownloadStatus::Remote {}) | None => (offline_state, None, None),
+            Some(_) => (offline_state, None, update),
+        })
     }
 }
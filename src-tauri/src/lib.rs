@@ -16,21 +16,26 @@ use cleanup::{cleanup_and_exit, quit};
 use commands::fetch_state;
 use database::commands::{
     add_download_dir, delete_download_dir, fetch_download_dir_stats, fetch_settings,
-    fetch_system_data, update_settings,
-};
-use database::db::{
-    borrow_db_checked, borrow_db_mut_checked, DatabaseInterface, GameDownloadStatus, DATA_ROOT_DIR,
+    fetch_system_data, set_download_dir_depot, update_settings,
 };
+use database::db::{borrow_db_checked, borrow_db_mut_checked, DatabaseInterface, DATA_ROOT_DIR};
 use download_manager::commands::{
     cancel_game, move_download_in_queue, pause_downloads, resume_downloads,
 };
+use download_manager::download_concurrency::set_max_download_threads;
 use download_manager::download_manager::DownloadManager;
 use download_manager::download_manager_builder::DownloadManagerBuilder;
 use games::commands::{
-    fetch_game, fetch_game_status, fetch_game_verion_options, fetch_library, uninstall_game,
+    adopt_orphaned_game_data, delete_orphaned_game_data, fetch_game, fetch_game_status,
+    fetch_game_verion_options, fetch_installed_versions, fetch_library, fetch_rollback_version,
+    fix_game_data, move_game, reconcile_game_data, rollback_game, scan_orphaned_game_data,
+    set_default_game_version, uninstall_game,
+};
+use games::downloads::commands::{
+    download_game, fetch_game_update_policy, set_game_update_policy, update_game,
 };
-use games::downloads::commands::download_game;
 use games::library::Game;
+use games::reconcile::reconcile;
 use http::Response;
 use http::{header::*, response::Builder as ResponseBuilder};
 use log::{debug, info, warn, LevelFilter};
@@ -41,18 +46,19 @@ use log4rs::encode::pattern::PatternEncoder;
 use log4rs::Config;
 use process::commands::{kill_game, launch_game};
 use process::process_manager::ProcessManager;
-use remote::auth::{self, generate_authorization_header, recieve_handshake};
+use remote::auth::{self, recieve_handshake};
 use remote::commands::{
-    auth_initiate, gen_drop_url, manual_recieve_handshake, retry_connect, sign_out, use_remote,
+    add_server_profile, auth_initiate, fetch_credential_status, fetch_server_profiles,
+    gen_drop_url, manual_recieve_handshake, remove_server_profile, retry_connect,
+    set_credential_protection, sign_out, switch_server_profile, unlock_credentials, use_remote,
 };
-use remote::requests::make_request;
+use remote::requests::{make_game_request, make_request, send_request};
 use serde::{Deserialize, Serialize};
 use std::env;
-use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{LazyLock, Mutex},
 };
 use tauri::menu::{Menu, MenuItem, PredefinedMenuItem};
@@ -69,6 +75,8 @@ pub enum AppStatus {
     SignedIn,
     SignedInNeedsReauth,
     ServerUnavailable,
+    CredentialsLocked,
+    CredentialStoreUnavailable,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -129,6 +137,10 @@ fn setup(handle: AppHandle) -> AppState<'static> {
     let download_manager = Arc::new(DownloadManagerBuilder::build(handle.clone()));
     let process_manager = Arc::new(Mutex::new(ProcessManager::new(handle.clone())));
 
+    if let Ok(db_handle) = borrow_db_checked() {
+        set_max_download_threads(db_handle.settings.max_download_threads);
+    }
+
     debug!("checking if database is set up");
     let is_set_up = DB.database_is_set_up();
     if !is_set_up {
@@ -146,47 +158,11 @@ fn setup(handle: AppHandle) -> AppState<'static> {
     // TODO: Account for possible failure
     let (app_status, user) = auth::setup();
 
-    let db_handle = borrow_db_checked();
-    let mut missing_games = Vec::new();
-    let statuses = db_handle.applications.game_statuses.clone();
-    drop(db_handle);
-    for (game_id, status) in statuses.into_iter() {
-        match status {
-            database::db::GameDownloadStatus::Remote {} => {}
-            database::db::GameDownloadStatus::SetupRequired {
-                version_name: _,
-                install_dir,
-            } => {
-                let install_dir_path = Path::new(&install_dir);
-                if !install_dir_path.exists() {
-                    missing_games.push(game_id);
-                }
-            }
-            database::db::GameDownloadStatus::Installed {
-                version_name: _,
-                install_dir,
-            } => {
-                let install_dir_path = Path::new(&install_dir);
-                if !install_dir_path.exists() {
-                    missing_games.push(game_id);
-                }
-            }
-        }
-    }
-
-    info!("detected games missing: {:?}", missing_games);
-
-    let mut db_handle = borrow_db_mut_checked();
-    for game_id in missing_games {
-        db_handle
-            .applications
-            .game_statuses
-            .entry(game_id)
-            .and_modify(|v| *v = GameDownloadStatus::Remote {});
+    // Nothing is queued or running yet
+    if let Err(e) = reconcile(&HashSet::new()) {
+        warn!("could not check game data: {}", e);
     }
 
-    drop(db_handle);
-
     debug!("finished setup!");
 
     // Sync autostart state
@@ -234,24 +210,45 @@ pub fn run() {
             retry_connect,
             manual_recieve_handshake,
             sign_out,
+            fetch_credential_status,
+            set_credential_protection,
+            unlock_credentials,
             // Remote
             use_remote,
             gen_drop_url,
+            fetch_server_profiles,
+            add_server_profile,
+            switch_server_profile,
+            remove_server_profile,
             // Library
             fetch_library,
             fetch_game,
             add_download_dir,
             delete_download_dir,
+            set_download_dir_depot,
             fetch_download_dir_stats,
             fetch_game_status,
             fetch_game_verion_options,
             // Downloads
             download_game,
+            update_game,
+            fetch_game_update_policy,
+            set_game_update_policy,
             move_download_in_queue,
             pause_downloads,
             resume_downloads,
             cancel_game,
             uninstall_game,
+            fetch_installed_versions,
+            set_default_game_version,
+            move_game,
+            rollback_game,
+            fetch_rollback_version,
+            scan_orphaned_game_data,
+            delete_orphaned_game_data,
+            adopt_orphaned_game_data,
+            reconcile_game_data,
+            fix_game_data,
             // Processes
             launch_game,
             kill_game,
@@ -269,6 +266,7 @@ pub fn run() {
             let state = setup(handle);
             debug!("initialized drop client");
             app.manage(Mutex::new(state));
+            update_checker::spawn_update_checker(app.handle().clone());
 
             #[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
             {
@@ -334,11 +332,15 @@ pub fn run() {
                 .build(app)
                 .expect("error while setting up tray menu");
 
-            {
-                let mut db_handle = borrow_db_mut_checked();
+            if let Ok(mut db_handle) = borrow_db_mut_checked() {
                 if let Some(original) = db_handle.prev_database.take() {
+                    let reason = db_handle
+                        .prev_database_reason
+                        .take()
+                        .unwrap_or_else(|| "Database corrupted".to_string());
                     warn!(
-                        "Database corrupted. Original file at {}",
+                        "{}. Original file at {}",
+                        reason,
                         original
                             .canonicalize()
                             .unwrap()
@@ -346,11 +348,12 @@ pub fn run() {
                             .to_string()
                     );
                     app.dialog()
-                        .message(
-                            "Database corrupted. A copy has been saved at: ".to_string()
-                                + original.to_str().unwrap(),
-                        )
-                        .title("Database corrupted")
+                        .message(format!(
+                            "{}. A copy has been saved at: {}",
+                            reason,
+                            original.to_str().unwrap()
+                        ))
+                        .title("Database could not be loaded")
                         .show(|_| {});
                 }
             }
@@ -360,14 +363,18 @@ pub fn run() {
         .register_asynchronous_uri_scheme_protocol("object", move |_ctx, request, responder| {
             // Drop leading /
             let object_id = &request.uri().path()[1..];
-
-            let header = generate_authorization_header();
-            let client: reqwest::blocking::Client = reqwest::blocking::Client::new();
-            let response = make_request(&client, &["/api/v1/client/object/", object_id], &[], |f| {
-                f.header("Authorization", header)
-            })
-            .unwrap()
-            .send();
+            // Objects come from the server of the game they're shown for, if any
+            let game_id = request
+                .uri()
+                .query()
+                .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("game=")));
+
+            let path_components = ["/api/v1/client/object/", object_id];
+            let response = match game_id {
+                Some(game_id) => make_game_request(game_id, &path_components, &[], |f| f),
+                None => make_request(&path_components, &[], |f| f),
+            }
+            .and_then(send_request);
             if response.is_err() {
                 warn!(
                     "failed to fetch object with error: {}",
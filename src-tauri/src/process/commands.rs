@@ -5,6 +5,7 @@ use crate::{error::process_error::ProcessError, AppState};
 #[tauri::command]
 pub fn launch_game(
     id: String,
+    version: Option<String>,
     state: tauri::State<'_, Mutex<AppState>>,
 ) -> Result<(), ProcessError> {
     let state_lock = state.lock().unwrap();
@@ -16,7 +17,7 @@ pub fn launch_game(
     //    download_type: DownloadType::Game,
     //};
 
-    match process_manager_lock.launch_process(id) {
+    match process_manager_lock.launch_process(id, version) {
         Ok(_) => {}
         Err(e) => return Err(e),
     };
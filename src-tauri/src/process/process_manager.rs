@@ -16,12 +16,16 @@ use umu_wrapper_lib::command_builder::UmuCommandBuilder;
 
 use crate::{
     database::db::{
-        borrow_db_mut_checked, ApplicationTransientStatus, GameDownloadStatus, GameVersion, DATA_ROOT_DIR
+        borrow_db_checked, borrow_db_mut_checked, ApplicationTransientStatus, GameDownloadStatus,
+        GameVersion, DATA_ROOT_DIR,
     },
     download_manager::downloadable_metadata::{DownloadType, DownloadableMetadata},
     error::process_error::ProcessError,
-    games::{library::push_game_update, state::GameStatusManager},
-    AppState, DB,
+    games::{
+        library::{get_installed_status, push_game_update, replace_installed_status},
+        state::GameStatusManager,
+    },
+    AppState,
 };
 
 pub struct ProcessManager<'a> {
@@ -66,7 +70,11 @@ impl ProcessManager<'_> {
         }
     }
 
-    fn process_command(&self, install_dir: &String, command: Vec<String>) -> (PathBuf, Vec<String>) {
+    fn process_command(
+        &self,
+        install_dir: &String,
+        command: Vec<String>,
+    ) -> (PathBuf, Vec<String>) {
         let root = &command[0];
 
         let install_dir = Path::new(install_dir);
@@ -94,7 +102,16 @@ impl ProcessManager<'_> {
         }
     }
 
-    fn on_process_finish(&mut self, game_id: String, result: Result<ExitStatus, std::io::Error>) {
+    pub fn running_game_ids(&self) -> impl Iterator<Item = &String> {
+        self.processes.keys()
+    }
+
+    fn on_process_finish(
+        &mut self,
+        meta: DownloadableMetadata,
+        result: Result<ExitStatus, std::io::Error>,
+    ) {
+        let game_id = meta.id.clone();
         if !self.processes.contains_key(&game_id) {
             warn!("process on_finish was called, but game_id is no longer valid. finished with result: {:?}", result);
             return;
@@ -104,16 +121,13 @@ impl ProcessManager<'_> {
 
         self.processes.remove(&game_id);
 
-        let mut db_handle = borrow_db_mut_checked();
-        let meta = db_handle
-            .applications
-            .installed_game_version
-            .get(&game_id)
-            .cloned()
-            .unwrap();
+        let Ok(mut db_handle) = borrow_db_mut_checked() else {
+            return;
+        };
         db_handle.applications.transient_statuses.remove(&meta);
 
-        let current_state = db_handle.applications.game_statuses.get(&game_id).cloned();
+        let current_state =
+            get_installed_status(&db_handle.applications, &game_id, meta.version.as_ref());
         if let Some(saved_state) = current_state {
             if let GameDownloadStatus::SetupRequired {
                 version_name,
@@ -122,8 +136,9 @@ impl ProcessManager<'_> {
             {
                 if let Ok(exit_code) = result {
                     if exit_code.success() {
-                        db_handle.applications.game_statuses.insert(
-                            game_id.clone(),
+                        replace_installed_status(
+                            &mut db_handle.applications,
+                            &game_id,
                             GameDownloadStatus::Installed {
                                 version_name: version_name.to_string(),
                                 install_dir: install_dir.to_string(),
@@ -135,9 +150,9 @@ impl ProcessManager<'_> {
         }
         drop(db_handle);
 
-        let status = GameStatusManager::fetch_state(&game_id);
-
-        push_game_update(&self.app_handle, &game_id, status);
+        if let Ok(status) = GameStatusManager::fetch_state(&game_id) {
+            push_game_update(&self.app_handle, &game_id, status);
+        }
 
         // TODO better management
     }
@@ -149,19 +164,22 @@ impl ProcessManager<'_> {
             .contains_key(&(current.clone(), platform.clone())))
     }
 
-    pub fn launch_process(&mut self, game_id: String) -> Result<(), ProcessError> {
+    /// Launches the given installed version of a game, or the default
+    /// one. Only one version of a game can run at a time
+    pub fn launch_process(
+        &mut self,
+        game_id: String,
+        version: Option<String>,
+    ) -> Result<(), ProcessError> {
         if self.processes.contains_key(&game_id) {
             return Err(ProcessError::AlreadyRunning);
         }
 
-        let version = match DB
-            .borrow_data()
-            .unwrap()
-            .applications
-            .game_statuses
-            .get(&game_id)
-            .cloned()
-        {
+        let version = match get_installed_status(
+            &borrow_db_checked()?.applications,
+            &game_id,
+            version.as_ref(),
+        ) {
             Some(GameDownloadStatus::Installed { version_name, .. }) => version_name,
             Some(GameDownloadStatus::SetupRequired { .. }) => {
                 return Err(ProcessError::SetupRequired)
@@ -174,19 +192,16 @@ impl ProcessManager<'_> {
             download_type: DownloadType::Game,
         };
 
-        let mut db_lock = borrow_db_mut_checked();
+        let mut db_lock = borrow_db_mut_checked()?;
         debug!(
             "Launching process {:?} with games {:?}",
             &game_id, db_lock.applications.game_versions
         );
 
-        let game_status = db_lock
-            .applications
-            .game_statuses
-            .get(&game_id)
+        let game_status = get_installed_status(&db_lock.applications, &game_id, Some(&version))
             .ok_or(ProcessError::NotInstalled)?;
 
-        let (version_name, install_dir) = match game_status {
+        let (version_name, install_dir) = match &game_status {
             GameDownloadStatus::Installed {
                 version_name,
                 install_dir,
@@ -198,7 +213,6 @@ impl ProcessManager<'_> {
             _ => return Err(ProcessError::NotDownloaded),
         };
 
-
         let game_version = db_lock
             .applications
             .game_versions
@@ -209,21 +223,21 @@ impl ProcessManager<'_> {
 
         let mut command: Vec<String> = Vec::new();
 
-        match game_status {
+        match &game_status {
             GameDownloadStatus::Installed {
                 version_name: _,
                 install_dir: _,
             } => {
                 command.extend([game_version.launch_command.clone()]);
                 command.extend(game_version.launch_args.clone());
-            },
+            }
             GameDownloadStatus::SetupRequired {
                 version_name: _,
                 install_dir: _,
             } => {
                 command.extend([game_version.setup_command.clone()]);
                 command.extend(game_version.setup_args.clone());
-            },
+            }
             _ => panic!("unreachable code"),
         };
         info!("Command: {:?}", &command);
@@ -295,7 +309,7 @@ impl ProcessManager<'_> {
         push_game_update(
             &self.app_handle,
             &meta.id,
-            (None, Some(ApplicationTransientStatus::Running {})),
+            (None, Some(ApplicationTransientStatus::Running {}), None),
         );
 
         let wait_thread_handle = launch_process_handle.clone();
@@ -309,7 +323,7 @@ impl ProcessManager<'_> {
             let app_state_handle = app_state.lock().unwrap();
 
             let mut process_manager_handle = app_state_handle.process_manager.lock().unwrap();
-            process_manager_handle.on_process_finish(wait_thread_game_id.id, result);
+            process_manager_handle.on_process_finish(wait_thread_game_id, result);
 
             // As everything goes out of scope, they should get dropped
             // But just to explicit about it
@@ -374,8 +388,11 @@ impl ProcessHandler for UMULauncher {
     ) -> Result<Child, Error> {
         println!("Game override: .{:?}.", &game_version.umu_id_override);
         let game_id = match &game_version.umu_id_override {
-            Some(game_override) => game_override.is_empty().then_some(game_version.game_id.clone()).unwrap_or(game_override.clone()) ,
-            None => game_version.game_id.clone()
+            Some(game_override) => game_override
+                .is_empty()
+                .then_some(game_version.game_id.clone())
+                .unwrap_or(game_override.clone()),
+            None => game_version.game_id.clone(),
         };
         info!("Game ID: {}", game_id);
         UmuCommandBuilder::new(UMU_LAUNCHER_EXECUTABLE, launch_command)
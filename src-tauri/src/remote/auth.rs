@@ -1,21 +1,38 @@
-use std::{env, sync::Mutex};
+use std::{
+    collections::HashMap,
+    env,
+    sync::{LazyLock, Mutex},
+};
 
 use chrono::Utc;
-use log::{debug, error, warn};
-use openssl::{ec::EcKey, hash::MessageDigest, pkey::PKey, sign::Signer};
+use log::{debug, error, info, warn};
+use openssl::{
+    ec::EcKey,
+    error::ErrorStack,
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::Signer,
+};
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Emitter, Manager};
 use url::Url;
 
 use crate::{
-    database::db::{
-        borrow_db_checked, borrow_db_mut_checked, save_db, DatabaseAuth, DatabaseImpls,
+    database::{
+        credentials,
+        db::{borrow_db_checked, borrow_db_mut_checked, database_path, save_db, DatabaseAuth},
+        migrations::scrub_backups,
+        settings::ProxySettings,
     },
-    error::{drop_server_error::DropServerError, remote_access_error::RemoteAccessError},
-    AppState, AppStatus, User, DB,
+    error::remote_access_error::RemoteAccessError,
+    update_checker::request_update_check,
+    AppState, AppStatus, User,
 };
 
-use super::requests::make_request;
+use super::{
+    client::remote_client,
+    requests::{make_request, send_request},
+};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -39,53 +56,75 @@ struct HandshakeResponse {
     id: String,
 }
 
-// TODO: Change return value on Err
-pub fn sign_nonce(private_key: String, nonce: String) -> Result<String, ()> {
-    let client_private_key = EcKey::private_key_from_pem(private_key.as_bytes()).unwrap();
-    let pkey_private_key = PKey::from_ec_key(client_private_key).unwrap();
-
-    let mut signer = Signer::new(MessageDigest::sha256(), &pkey_private_key).unwrap();
-    signer.update(nonce.as_bytes()).unwrap();
-    let signature = signer.sign_to_vec().unwrap();
+// Parsing the key is slow enough to matter when it's done for every chunk,
+// so it's kept until the profile signs in as another client. Keyed by
+// profile name
+struct CachedSigningKey {
+    client_id: String,
+    key: PKey<Private>,
+}
+static SIGNING_KEYS: LazyLock<Mutex<HashMap<String, CachedSigningKey>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
 
-    let hex_signature = hex::encode(signature);
+pub fn sign_nonce(private_key: &PKey<Private>, nonce: &str) -> Result<String, ErrorStack> {
+    let mut signer = Signer::new(MessageDigest::sha256(), private_key)?;
+    signer.update(nonce.as_bytes())?;
+    let signature = signer.sign_to_vec()?;
 
-    Ok(hex_signature)
+    Ok(hex::encode(signature))
 }
 
-pub fn generate_authorization_header() -> String {
-    let certs = {
-        let db = borrow_db_checked();
-        db.auth.clone().unwrap()
-    };
+/// Signs a request for the server of the given profile
+pub fn generate_authorization_header(profile: &str) -> Result<String, RemoteAccessError> {
+    let db = borrow_db_checked()?;
+    let certs = db
+        .profiles
+        .get(profile)
+        .ok_or_else(|| RemoteAccessError::ProfileNotFound(profile.to_string()))?
+        .auth
+        .as_ref()
+        .ok_or_else(|| RemoteAccessError::SigningFailed("not signed in".to_string()))?;
+
+    let mut signing_keys = SIGNING_KEYS.lock().unwrap();
+    if signing_keys
+        .get(profile)
+        .is_none_or(|cached| cached.client_id != certs.client_id)
+    {
+        debug!("loading signing key for client {}", certs.client_id);
+        let private = match db.pending_private_keys.get(profile) {
+            Some(private) => private.clone(),
+            None => credentials::private_key(profile)?,
+        };
+        let key = EcKey::private_key_from_pem(private.as_bytes())
+            .and_then(PKey::from_ec_key)
+            .map_err(|e| RemoteAccessError::SigningFailed(e.to_string()))?;
+        signing_keys.insert(
+            profile.to_string(),
+            CachedSigningKey {
+                client_id: certs.client_id.clone(),
+                key,
+            },
+        );
+    }
 
     let nonce = Utc::now().timestamp_millis().to_string();
+    let signature = sign_nonce(&signing_keys[profile].key, &nonce)
+        .map_err(|e| RemoteAccessError::SigningFailed(e.to_string()))?;
 
-    let signature = sign_nonce(certs.private, nonce.clone()).unwrap();
+    Ok(format!("Nonce {} {} {}", certs.client_id, nonce, signature))
+}
 
-    format!("Nonce {} {} {}", certs.client_id, nonce, signature)
+/// Deletes the private key of a profile that's been signed out. Only worth
+/// a warning on failure, as the key is useless without the rest of the auth
+pub fn forget_private_key(profile: &str) {
+    SIGNING_KEYS.lock().unwrap().remove(profile);
+    if let Err(e) = credentials::remove_private_key(profile) {
+        warn!("could not remove private key for {}: {}", profile, e);
+    }
 }
 
 pub fn fetch_user() -> Result<User, RemoteAccessError> {
-    let base_url = DB.fetch_base_url();
-
-    let header = generate_authorization_header();
-
-    let client = reqwest::blocking::Client::new();
-    let response = make_request(&client, &["/api/v1/client/user"], &[], |f| {
-        f.header("Authorization", header)
-    })?
-    .send()?;
-    if response.status() != 200 {
-        let err: DropServerError = response.json()?;
-        warn!("{:?}", err);
-
-        if err.status_message == "Nonce expired" {
-            return Err(RemoteAccessError::OutOfSync);
-        }
-
-        return Err(RemoteAccessError::InvalidResponse(err));
-    }
+    let response = send_request(make_request(&["/api/v1/client/user"], &[], |f| f)?)?;
 
     response.json::<User>().map_err(|e| e.into())
 }
@@ -99,10 +138,7 @@ fn recieve_handshake_logic(app: &AppHandle, path: String) -> Result<(), RemoteAc
         ));
     }
 
-    let base_url = {
-        let handle = borrow_db_checked();
-        Url::parse(handle.base_url.as_str())?
-    };
+    let (profile, base_url) = active_profile_base_url()?;
 
     let client_id = path_chunks.get(1).unwrap();
     let token = path_chunks.get(2).unwrap();
@@ -112,20 +148,30 @@ fn recieve_handshake_logic(app: &AppHandle, path: String) -> Result<(), RemoteAc
     };
 
     let endpoint = base_url.join("/api/v1/client/auth/handshake")?;
-    let client = reqwest::blocking::Client::new();
-    let response = client.post(endpoint).json(&body).send()?;
-    debug!("handshake responsded with {}", response.status().as_u16());
+    let response = send_request(remote_client(&endpoint)?.post(endpoint).json(&body))?;
     let response_struct: HandshakeResponse = response.json()?;
 
+    // Stored first, so the database never refers to a key that isn't there
+    credentials::store_private_key(&profile, response_struct.private)?;
+    SIGNING_KEYS.lock().unwrap().remove(&profile);
     {
-        let mut handle = borrow_db_mut_checked();
-        handle.auth = Some(DatabaseAuth {
-            private: response_struct.private,
+        let mut handle = borrow_db_mut_checked()?;
+        let server = handle
+            .profiles
+            .get_mut(&profile)
+            .ok_or_else(|| RemoteAccessError::ProfileNotFound(profile.clone()))?;
+        server.auth = Some(DatabaseAuth {
             cert: response_struct.certificate,
             client_id: response_struct.id,
         });
+        // Superseded by the key that was just stored
+        handle.pending_private_keys.remove(&profile);
         drop(handle);
-        save_db();
+        save_db()?;
+    }
+    // The store just took a key, so it can take the other profiles' too
+    if let Err(e) = store_pending_secrets() {
+        warn!("could not move secrets to the credential store: {}", e);
     }
 
     {
@@ -134,6 +180,7 @@ fn recieve_handshake_logic(app: &AppHandle, path: String) -> Result<(), RemoteAc
         app_state_handle.status = AppStatus::SignedIn;
         app_state_handle.user = Some(fetch_user()?);
     }
+    request_update_check();
 
     Ok(())
 }
@@ -153,10 +200,7 @@ pub fn recieve_handshake(app: AppHandle, path: String) {
 }
 
 pub fn auth_initiate_logic() -> Result<(), RemoteAccessError> {
-    let base_url = {
-        let db_lock = borrow_db_checked();
-        Url::parse(&db_lock.base_url.clone())?
-    };
+    let (_, base_url) = active_profile_base_url()?;
 
     let endpoint = base_url.join("/api/v1/client/auth/initiate")?;
     let body = InitiateRequestBody {
@@ -164,15 +208,16 @@ pub fn auth_initiate_logic() -> Result<(), RemoteAccessError> {
         platform: env::consts::OS.to_string(),
     };
 
-    let client = reqwest::blocking::Client::new();
-    let response = client.post(endpoint.to_string()).json(&body).send()?;
-
-    if response.status() != 200 {
-        let data: DropServerError = response.json()?;
-        error!("could not start handshake: {}", data.status_message);
-
-        return Err(RemoteAccessError::HandshakeFailed(data.status_message));
-    }
+    let client = remote_client(&endpoint)?;
+    let response = send_request(client.post(endpoint).json(&body)).map_err(|e| {
+        error!("could not start handshake: {}", e);
+        match e {
+            RemoteAccessError::InvalidResponse(data) => {
+                RemoteAccessError::HandshakeFailed(data.status_message)
+            }
+            e => e,
+        }
+    })?;
 
     let redir_url = response.text()?;
     let complete_redir_url = base_url.join(&redir_url)?;
@@ -183,15 +228,92 @@ pub fn auth_initiate_logic() -> Result<(), RemoteAccessError> {
     Ok(())
 }
 
+// Signing in always happens against the active server
+fn active_profile_base_url() -> Result<(String, Url), RemoteAccessError> {
+    let handle = borrow_db_checked()?;
+    let profile = handle
+        .active_profile()
+        .ok_or_else(|| RemoteAccessError::ProfileNotFound(handle.active_profile.clone()))?;
+    Ok((
+        handle.active_profile.clone(),
+        Url::parse(&profile.base_url)?,
+    ))
+}
+
+/// Moves secrets still in the database into the credential store, then
+/// scrubs them from the database backups. These are the keys the store
+/// couldn't take during the migration, and a proxy password from before
+/// the store kept it
+fn store_pending_secrets() -> Result<(), RemoteAccessError> {
+    let (pending, mut settings) = {
+        let db = borrow_db_checked()?;
+        (db.pending_private_keys.clone(), db.settings.clone())
+    };
+    let has_password = matches!(
+        &settings.proxy,
+        ProxySettings::Manual {
+            credentials: Some(proxy_credentials),
+            ..
+        } if !proxy_credentials.password.is_empty()
+    );
+    if pending.is_empty() && !has_password {
+        return Ok(());
+    }
+    if has_password {
+        settings.store_proxy_password()?;
+        borrow_db_mut_checked()?.settings.proxy = settings.proxy;
+    }
+
+    for (profile, private) in pending {
+        // Profiles signed out since then don't need theirs any more
+        let signed_in = borrow_db_checked()?
+            .profiles
+            .get(&profile)
+            .is_some_and(|server| server.auth.is_some());
+        if signed_in {
+            credentials::store_private_key(&profile, private)?;
+        }
+        borrow_db_mut_checked()?
+            .pending_private_keys
+            .remove(&profile);
+    }
+    save_db()?;
+    scrub_backups(&database_path());
+
+    info!("moved pending secrets to the credential store");
+    Ok(())
+}
+
 pub fn setup() -> (AppStatus, Option<User>) {
-    let data = borrow_db_checked();
-    let auth = data.auth.clone();
-    drop(data);
+    let auth = borrow_db_checked().ok().and_then(|data| {
+        data.active_profile()
+            .and_then(|profile| profile.auth.clone())
+    });
+
+    if auth.is_none() {
+        // Other profiles may still be signed in
+        if let Err(e) = store_pending_secrets() {
+            warn!("could not move secrets to the credential store: {}", e);
+        }
+    } else {
+        match credentials::is_locked() {
+            Ok(true) => return (AppStatus::CredentialsLocked, None),
+            Ok(false) => {}
+            Err(e) => {
+                error!("could not open credential store: {}", e);
+                return (AppStatus::CredentialStoreUnavailable, None);
+            }
+        }
+        if let Err(e) = store_pending_secrets() {
+            error!("could not move secrets to the credential store: {}", e);
+            return (AppStatus::CredentialStoreUnavailable, None);
+        }
 
-    if auth.is_some() {
         let user_result = match fetch_user() {
             Ok(data) => data,
-            Err(RemoteAccessError::FetchError(_)) => return (AppStatus::ServerUnavailable, None),
+            Err(RemoteAccessError::FetchError(_) | RemoteAccessError::TlsError(_)) => {
+                return (AppStatus::ServerUnavailable, None)
+            }
             Err(_) => return (AppStatus::SignedInNeedsReauth, None),
         };
         return (AppStatus::SignedIn, Some(user_result));
@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{LazyLock, RwLock},
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use openssl::sha::sha256;
+use reqwest::{blocking, tls::TlsInfo, Certificate, NoProxy, Proxy};
+use url::Url;
+
+use crate::{
+    database::{
+        credentials,
+        db::borrow_db_checked,
+        settings::{
+            ProxyCredentials, ProxySettings, ServerTlsSettings, Settings, PROXY_PASSWORD_SECRET,
+        },
+    },
+    error::remote_access_error::RemoteAccessError,
+};
+
+/// One HTTP client per server (or mirror) origin, so connections are pooled
+/// between requests, while each server can trust its own certificates
+static REMOTE_CLIENTS: LazyLock<RwLock<HashMap<String, blocking::Client>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+/// Same as REMOTE_CLIENTS, for the async download pipeline
+static ASYNC_REMOTE_CLIENTS: LazyLock<RwLock<HashMap<String, reqwest::Client>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// The client for whichever server `url` points at. Cheap after the first
+/// call, as clones share the same connection pool
+pub fn remote_client(url: &Url) -> Result<blocking::Client, RemoteAccessError> {
+    cached_client::<blocking::ClientBuilder>(&REMOTE_CLIENTS, url)
+}
+
+/// Async version of remote_client. Must not be called from inside an async
+/// context, as building a client for a pinned server makes a blocking request
+pub fn async_remote_client(url: &Url) -> Result<reqwest::Client, RemoteAccessError> {
+    cached_client::<reqwest::ClientBuilder>(&ASYNC_REMOTE_CLIENTS, url)
+}
+
+/// Picks up changes to the connection settings. Requests already
+/// in flight finish on the old clients
+pub fn reset_remote_clients() {
+    REMOTE_CLIENTS.write().unwrap().clear();
+    ASYNC_REMOTE_CLIENTS.write().unwrap().clear();
+}
+
+fn cached_client<B: RemoteClientBuilder>(
+    cache: &RwLock<HashMap<String, B::Client>>,
+    url: &Url,
+) -> Result<B::Client, RemoteAccessError> {
+    let origin = url.origin().ascii_serialization();
+    if let Some(client) = cache.read().unwrap().get(&origin) {
+        return Ok(client.clone());
+    }
+
+    let settings = borrow_db_checked()?.settings.clone();
+    let client = build_client::<B>(&settings, url)?;
+    Ok(cache
+        .write()
+        .unwrap()
+        .entry(origin)
+        .or_insert(client)
+        .clone())
+}
+
+/// The options we set on both the blocking and the async client builders,
+/// which reqwest doesn't share a trait for
+trait RemoteClientBuilder: Sized {
+    type Client: Clone;
+
+    fn with_timeouts(settings: &Settings) -> Self;
+    fn no_proxy(self) -> Self;
+    fn proxy(self, proxy: Proxy) -> Self;
+    fn add_root_certificate(self, certificate: Certificate) -> Self;
+    /// Trusts `certificate` and nothing else, whatever host it's for
+    fn pin_certificate(self, certificate: Certificate) -> Self;
+    fn build(self) -> reqwest::Result<Self::Client>;
+}
+
+impl RemoteClientBuilder for blocking::ClientBuilder {
+    type Client = blocking::Client;
+
+    fn with_timeouts(settings: &Settings) -> Self {
+        blocking::Client::builder()
+            .connect_timeout(Duration::from_secs(settings.connect_timeout))
+            // The blocking client applies this to each read of a streamed body,
+            // rather than the whole download, so big chunks are fine
+            .timeout(Duration::from_secs(settings.read_timeout))
+    }
+    fn no_proxy(self) -> Self {
+        self.no_proxy()
+    }
+    fn proxy(self, proxy: Proxy) -> Self {
+        self.proxy(proxy)
+    }
+    fn add_root_certificate(self, certificate: Certificate) -> Self {
+        self.add_root_certificate(certificate)
+    }
+    fn pin_certificate(self, certificate: Certificate) -> Self {
+        self.tls_built_in_root_certs(false)
+            .add_root_certificate(certificate)
+            .danger_accept_invalid_hostnames(true)
+    }
+    fn build(self) -> reqwest::Result<Self::Client> {
+        self.build()
+    }
+}
+
+impl RemoteClientBuilder for reqwest::ClientBuilder {
+    type Client = reqwest::Client;
+
+    fn with_timeouts(settings: &Settings) -> Self {
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(settings.connect_timeout))
+            .read_timeout(Duration::from_secs(settings.read_timeout))
+    }
+    fn no_proxy(self) -> Self {
+        self.no_proxy()
+    }
+    fn proxy(self, proxy: Proxy) -> Self {
+        self.proxy(proxy)
+    }
+    fn add_root_certificate(self, certificate: Certificate) -> Self {
+        self.add_root_certificate(certificate)
+    }
+    fn pin_certificate(self, certificate: Certificate) -> Self {
+        self.tls_built_in_root_certs(false)
+            .add_root_certificate(certificate)
+            .danger_accept_invalid_hostnames(true)
+    }
+    fn build(self) -> reqwest::Result<Self::Client> {
+        self.build()
+    }
+}
+
+fn base_builder<B: RemoteClientBuilder>(settings: &Settings) -> Result<B, RemoteAccessError> {
+    apply_proxy(B::with_timeouts(settings), &settings.proxy)
+}
+
+fn apply_proxy<B: RemoteClientBuilder>(
+    builder: B,
+    proxy_settings: &ProxySettings,
+) -> Result<B, RemoteAccessError> {
+    let (http, https, socks5, no_proxy, credentials) = match proxy_settings {
+        // reqwest reads the environment variables by itself
+        ProxySettings::System {} => return Ok(builder),
+        ProxySettings::Direct {} => return Ok(builder.no_proxy()),
+        ProxySettings::Manual {
+            http,
+            https,
+            socks5,
+            no_proxy,
+            credentials,
+        } => (http, https, socks5, no_proxy, credentials),
+    };
+
+    let password = credentials.as_ref().map(proxy_password);
+
+    // So that a manual configuration with nothing filled in connects
+    // directly, rather than falling back to the environment variables
+    let mut builder = builder.no_proxy();
+    // reqwest tries these in order, so the SOCKS5 catch-all goes last
+    let proxies = [
+        configured_proxy(http).map(|url| (url, Proxy::http(url))),
+        configured_proxy(https).map(|url| (url, Proxy::https(url))),
+        configured_proxy(socks5).map(|url| (url, Proxy::all(url))),
+    ];
+    for (url, proxy) in proxies.into_iter().flatten() {
+        let mut proxy = proxy
+            .map_err(|e| RemoteAccessError::InvalidProxy(format!("{}: {}", url, e)))?
+            .no_proxy(NoProxy::from_string(no_proxy));
+        if let (Some(credentials), Some(password)) = (credentials, &password) {
+            proxy = proxy.basic_auth(&credentials.username, password);
+        }
+        builder = builder.proxy(proxy);
+    }
+
+    Ok(builder)
+}
+
+// The password is kept in the credential store, unless it's from a
+// database that hasn't been moved over yet
+fn proxy_password(proxy_credentials: &ProxyCredentials) -> String {
+    if !proxy_credentials.password.is_empty() {
+        return proxy_credentials.password.clone();
+    }
+    credentials::secret(PROXY_PASSWORD_SECRET)
+        .unwrap_or_else(|e| {
+            warn!(
+                "could not read proxy password, connecting without it: {}",
+                e
+            );
+            None
+        })
+        .unwrap_or_default()
+}
+
+// Cleared fields can come back from the frontend as empty strings
+fn configured_proxy(url: &Option<String>) -> Option<&str> {
+    url.as_deref().filter(|url| !url.is_empty())
+}
+
+fn build_client<B: RemoteClientBuilder>(
+    settings: &Settings,
+    url: &Url,
+) -> Result<B::Client, RemoteAccessError> {
+    debug!(
+        "building remote client for {} with {}s connect and {}s read timeouts",
+        url.origin().ascii_serialization(),
+        settings.connect_timeout,
+        settings.read_timeout
+    );
+    let mut builder = base_builder::<B>(settings)?;
+
+    let tls_settings = match server_tls_settings(settings, url) {
+        Some(tls_settings) if url.scheme() == "https" => tls_settings,
+        _ => return Ok(builder.build()?),
+    };
+    if let Some(fingerprint) = &tls_settings.pinned_fingerprint {
+        // The pinned certificate is the only one trusted, and it identifies
+        // the server by itself, so the hostname doesn't need to match
+        let certificate = fetch_pinned_certificate(settings, url, fingerprint)?;
+        builder = builder.pin_certificate(certificate);
+    } else if let Some(ca_bundle) = &tls_settings.ca_bundle {
+        let pem = fs::read(ca_bundle).map_err(|e| {
+            RemoteAccessError::TlsError(format!(
+                "could not read CA bundle {}: {}",
+                ca_bundle.display(),
+                e
+            ))
+        })?;
+        let certificates = Certificate::from_pem_bundle(&pem).map_err(|e| {
+            RemoteAccessError::TlsError(format!("invalid CA bundle {}: {}", ca_bundle.display(), e))
+        })?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    Ok(builder.build()?)
+}
+
+// Entries are keyed by server address, so compare origins rather than
+// strings to ignore paths, trailing slashes and default ports
+fn server_tls_settings<'a>(settings: &'a Settings, url: &Url) -> Option<&'a ServerTlsSettings> {
+    settings
+        .server_tls
+        .iter()
+        .find(|(server, _)| Url::parse(server).is_ok_and(|server| server.origin() == url.origin()))
+        .map(|(_, tls_settings)| tls_settings)
+}
+
+/// Connects without verifying the server's certificate to find out what it
+/// is, then checks it against the pin. Nothing is sent on this connection
+/// besides a bare HEAD request. Meant for self-signed certificates, as the
+/// pinned certificate becomes the only trusted root
+fn fetch_pinned_certificate(
+    settings: &Settings,
+    url: &Url,
+    fingerprint: &str,
+) -> Result<Certificate, RemoteAccessError> {
+    let probe = base_builder::<blocking::ClientBuilder>(settings)?
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .build()?;
+    let response = probe.head(url.join("/")?).send()?;
+    let der = response
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(|tls_info| tls_info.peer_certificate())
+        .ok_or_else(|| {
+            RemoteAccessError::TlsError("server did not present a certificate".to_string())
+        })?;
+
+    let actual = hex::encode(sha256(der));
+    if normalise_fingerprint(fingerprint) != actual {
+        return Err(RemoteAccessError::TlsError(format!(
+            "the server's certificate (SHA-256 {}) does not match the pinned fingerprint",
+            actual
+        )));
+    }
+    info!(
+        "pinned certificate for {} matched",
+        url.origin().ascii_serialization()
+    );
+
+    Ok(Certificate::from_der(der)?)
+}
+
+// Fingerprints are usually copied with colons or spaces between bytes
+pub fn normalise_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect::<String>()
+        .to_lowercase()
+}
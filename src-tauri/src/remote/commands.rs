@@ -4,13 +4,27 @@ use tauri::{AppHandle, Emitter, Manager};
 use url::Url;
 
 use crate::{
-    database::db::{borrow_db_checked, borrow_db_mut_checked, save_db},
-    error::remote_access_error::RemoteAccessError,
+    database::{
+        credentials::{
+            credential_status, set_protection, unlock, CredentialStatus, ProtectionKind,
+        },
+        db::{borrow_db_checked, borrow_db_mut_checked, save_db},
+    },
+    error::{
+        credential_error::CredentialError, database_error::DatabaseError,
+        profile_error::ProfileError, remote_access_error::RemoteAccessError,
+    },
+    update_checker::request_update_check,
     AppState, AppStatus,
 };
 
 use super::{
-    auth::{auth_initiate_logic, recieve_handshake, setup},
+    auth::{auth_initiate_logic, forget_private_key, recieve_handshake, setup},
+    client::reset_remote_clients,
+    profiles::{
+        add_server_profile_logic, fetch_server_profiles_logic, remove_server_profile_logic,
+        switch_server_profile_logic, ServerProfileInfo,
+    },
     remote::use_remote_logic,
 };
 
@@ -22,12 +36,21 @@ pub fn use_remote(
     use_remote_logic(url, state)
 }
 
+/// Links to a page on the active server, or on the server of the given game
 #[tauri::command]
-pub fn gen_drop_url(path: String) -> Result<String, RemoteAccessError> {
+pub fn gen_drop_url(path: String, game_id: Option<String>) -> Result<String, RemoteAccessError> {
     let base_url = {
-        let handle = borrow_db_checked();
+        let handle = borrow_db_checked()?;
+        let profile_name = match &game_id {
+            Some(game_id) => handle.game_profile(game_id),
+            None => handle.active_profile.as_str(),
+        };
+        let profile = handle
+            .profiles
+            .get(profile_name)
+            .ok_or_else(|| RemoteAccessError::ProfileNotFound(profile_name.to_string()))?;
 
-        Url::parse(&handle.base_url).map_err(RemoteAccessError::ParsingError)?
+        Url::parse(&profile.base_url).map_err(RemoteAccessError::ParsingError)?
     };
 
     let url = base_url.join(&path).unwrap();
@@ -36,13 +59,17 @@ pub fn gen_drop_url(path: String) -> Result<String, RemoteAccessError> {
 }
 
 #[tauri::command]
-pub fn sign_out(app: AppHandle) {
+pub fn sign_out(app: AppHandle) -> Result<(), DatabaseError> {
     // Clear auth from database
     {
-        let mut handle = borrow_db_mut_checked();
-        handle.auth = None;
+        let mut handle = borrow_db_mut_checked()?;
+        if let Some(profile) = handle.active_profile_mut() {
+            profile.auth = None;
+        }
+        let profile = handle.active_profile.clone();
         drop(handle);
-        save_db();
+        save_db()?;
+        forget_private_key(&profile);
     }
 
     // Update app state
@@ -55,16 +82,22 @@ pub fn sign_out(app: AppHandle) {
 
     // Emit event for frontend
     app.emit("auth/signedout", ()).unwrap();
+    Ok(())
 }
 
 #[tauri::command]
 pub fn retry_connect(state: tauri::State<'_, Mutex<AppState>>) {
     let (app_status, user) = setup();
+    let signed_in = matches!(app_status, AppStatus::SignedIn);
 
     let mut guard = state.lock().unwrap();
     guard.status = app_status;
     guard.user = user;
     drop(guard);
+
+    if signed_in {
+        request_update_check();
+    }
 }
 
 #[tauri::command]
@@ -76,3 +109,56 @@ pub fn auth_initiate() -> Result<(), RemoteAccessError> {
 pub fn manual_recieve_handshake(app: AppHandle, token: String) {
     recieve_handshake(app, format!("handshake/{}", token));
 }
+
+#[tauri::command]
+pub fn fetch_credential_status() -> Result<CredentialStatus, CredentialError> {
+    credential_status()
+}
+
+#[tauri::command]
+pub fn set_credential_protection(
+    protection: ProtectionKind,
+    passphrase: Option<String>,
+) -> Result<(), CredentialError> {
+    set_protection(protection, passphrase)
+}
+
+/// Unlocks a passphrase protected credential store, then signs in with it
+#[tauri::command]
+pub fn unlock_credentials(
+    passphrase: String,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<(), CredentialError> {
+    unlock(&passphrase)?;
+    // Clients built while locked are missing the proxy password
+    reset_remote_clients();
+    retry_connect(state);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn fetch_server_profiles() -> Result<Vec<ServerProfileInfo>, DatabaseError> {
+    fetch_server_profiles_logic()
+}
+
+#[tauri::command]
+pub fn add_server_profile(
+    name: String,
+    url: String,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<(), ProfileError> {
+    add_server_profile_logic(name, url, &state)
+}
+
+#[tauri::command]
+pub fn switch_server_profile(
+    name: String,
+    state: tauri::State<'_, Mutex<AppState>>,
+) -> Result<(), ProfileError> {
+    switch_server_profile_logic(name, &state)
+}
+
+#[tauri::command]
+pub fn remove_server_profile(name: String) -> Result<(), ProfileError> {
+    remove_server_profile_logic(name)
+}
@@ -1,4 +1,6 @@
 pub mod auth;
+pub mod client;
 pub mod commands;
+pub mod profiles;
 pub mod remote;
 pub mod requests;
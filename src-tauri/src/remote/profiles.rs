@@ -0,0 +1,150 @@
+use std::sync::Mutex;
+
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    database::db::{
+        borrow_db_checked, borrow_db_mut_checked, save_db, GameDownloadStatus, ServerProfile,
+    },
+    error::{database_error::DatabaseError, profile_error::ProfileError},
+    update_checker::request_update_check,
+    AppState, AppStatus,
+};
+
+use super::{
+    auth::{forget_private_key, setup},
+    remote::check_drop_server,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerProfileInfo {
+    name: String,
+    base_url: String,
+    signed_in: bool,
+    active: bool,
+}
+
+pub fn fetch_server_profiles_logic() -> Result<Vec<ServerProfileInfo>, DatabaseError> {
+    let db_handle = borrow_db_checked()?;
+    let mut profiles: Vec<ServerProfileInfo> = db_handle
+        .profiles
+        .iter()
+        .map(|(name, profile)| ServerProfileInfo {
+            name: name.clone(),
+            base_url: profile.base_url.clone(),
+            signed_in: profile.auth.is_some(),
+            active: *name == db_handle.active_profile,
+        })
+        .collect();
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(profiles)
+}
+
+/// Adds a profile for another server and switches to it, so it can be
+/// signed in to
+pub fn add_server_profile_logic(
+    name: String,
+    url: String,
+    state: &Mutex<AppState>,
+) -> Result<(), ProfileError> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(ProfileError::InvalidName);
+    }
+    if borrow_db_checked()?.profiles.contains_key(&name) {
+        return Err(ProfileError::AlreadyExists(name));
+    }
+
+    let base_url = check_drop_server(&url)?;
+
+    let mut db_handle = borrow_db_mut_checked()?;
+    db_handle.profiles.insert(
+        name.clone(),
+        ServerProfile {
+            base_url: base_url.to_string(),
+            auth: None,
+        },
+    );
+    drop(db_handle);
+    save_db()?;
+
+    info!("added server profile {} for {}", name, base_url);
+    switch_server_profile_logic(name, state)
+}
+
+/// Makes another profile the active one. Games keep using the profile
+/// they were fetched from
+pub fn switch_server_profile_logic(
+    name: String,
+    state: &Mutex<AppState>,
+) -> Result<(), ProfileError> {
+    let mut db_handle = borrow_db_mut_checked()?;
+    if !db_handle.profiles.contains_key(&name) {
+        return Err(ProfileError::NotFound(name));
+    }
+    db_handle.active_profile = name;
+    drop(db_handle);
+    save_db()?;
+
+    let (app_status, user) = setup();
+    let signed_in = matches!(app_status, AppStatus::SignedIn);
+    let mut guard = state.lock().unwrap();
+    guard.status = app_status;
+    guard.user = user;
+    drop(guard);
+
+    // Only once the new status is in place, as the check relies on it
+    if signed_in {
+        request_update_check();
+    }
+
+    Ok(())
+}
+
+pub fn remove_server_profile_logic(name: String) -> Result<(), ProfileError> {
+    let mut db_handle = borrow_db_mut_checked()?;
+    if !db_handle.profiles.contains_key(&name) {
+        return Err(ProfileError::NotFound(name));
+    }
+    if db_handle.active_profile == name {
+        return Err(ProfileError::ActiveProfile(name));
+    }
+
+    // Installed games would have nowhere to fetch updates from
+    let applications = &db_handle.applications;
+    let owned_games: Vec<String> = applications
+        .game_sources
+        .iter()
+        .filter(|(_, source)| source.profile == name)
+        .map(|(game_id, _)| game_id.clone())
+        .collect();
+    let in_use = owned_games.iter().any(|game_id| {
+        applications
+            .game_statuses
+            .get(game_id)
+            .is_some_and(|status| !matches!(status, GameDownloadStatus::Remote {}))
+            || applications.side_by_side_installs.contains_key(game_id)
+            || applications
+                .transient_statuses
+                .keys()
+                .any(|meta| meta.id == *game_id)
+    });
+    if in_use {
+        return Err(ProfileError::InUse(name));
+    }
+
+    let removed = db_handle.profiles.remove(&name);
+    for game_id in owned_games {
+        db_handle.applications.game_sources.remove(&game_id);
+    }
+    drop(db_handle);
+    save_db()?;
+    if removed.is_some_and(|profile| profile.auth.is_some()) {
+        forget_private_key(&name);
+    }
+
+    info!("removed server profile {}", name);
+    Ok(())
+}
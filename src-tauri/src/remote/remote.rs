@@ -5,27 +5,71 @@ use serde::Deserialize;
 use url::Url;
 
 use crate::{
-    database::db::{borrow_db_mut_checked, save_db},
+    database::db::{borrow_db_mut_checked, save_db, ServerProfile, DEFAULT_PROFILE_NAME},
     error::remote_access_error::RemoteAccessError,
     AppState, AppStatus,
 };
 
+use super::{auth::forget_private_key, client::remote_client, requests::send_request};
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct DropHealthcheck {
     app_name: String,
 }
 
+/// Points the active server profile at a new server, creating the first
+/// profile if there isn't one yet
 pub fn use_remote_logic(
     url: String,
     state: tauri::State<'_, Mutex<AppState<'_>>>,
 ) -> Result<(), RemoteAccessError> {
+    let base_url = check_drop_server(&url)?;
+
+    let mut app_state = state.lock().unwrap();
+    app_state.status = AppStatus::SignedOut;
+    drop(app_state);
+
+    let mut db_state = borrow_db_mut_checked()?;
+    let mut signed_out = false;
+    match db_state.active_profile_mut() {
+        Some(profile) => {
+            // Credentials are only valid for the server that issued them
+            if profile.base_url != base_url.as_str() {
+                signed_out = profile.auth.take().is_some();
+            }
+            profile.base_url = base_url.to_string();
+        }
+        None => {
+            db_state.profiles.insert(
+                DEFAULT_PROFILE_NAME.to_string(),
+                ServerProfile {
+                    base_url: base_url.to_string(),
+                    auth: None,
+                },
+            );
+            db_state.active_profile = DEFAULT_PROFILE_NAME.to_string();
+        }
+    }
+    let profile = db_state.active_profile.clone();
+    drop(db_state);
+
+    save_db()?;
+    if signed_out {
+        forget_private_key(&profile);
+    }
+
+    Ok(())
+}
+
+/// Checks that a URL points at a Drop server
+pub fn check_drop_server(url: &str) -> Result<Url, RemoteAccessError> {
     debug!("connecting to url {}", url);
-    let base_url = Url::parse(&url)?;
+    let base_url = Url::parse(url)?;
 
     // Test Drop url
     let test_endpoint = base_url.join("/api/v1")?;
-    let response = reqwest::blocking::get(test_endpoint.to_string())?;
+    let response = send_request(remote_client(&test_endpoint)?.get(test_endpoint))?;
 
     let result: DropHealthcheck = response.json()?;
 
@@ -34,15 +78,5 @@ pub fn use_remote_logic(
         return Err(RemoteAccessError::InvalidEndpoint);
     }
 
-    let mut app_state = state.lock().unwrap();
-    app_state.status = AppStatus::SignedOut;
-    drop(app_state);
-
-    let mut db_state = borrow_db_mut_checked();
-    db_state.base_url = base_url.to_string();
-    drop(db_state);
-
-    save_db();
-
-    Ok(())
+    Ok(base_url)
 }
@@ -1,14 +1,117 @@
-use reqwest::blocking::{Client, RequestBuilder};
+use std::time::Instant;
 
-use crate::{database::db::DatabaseImpls, error::remote_access_error::RemoteAccessError, DB};
+use log::{debug, warn};
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    Method, StatusCode,
+};
+use url::Url;
 
+use crate::{
+    database::db::borrow_db_checked,
+    error::{
+        database_error::DatabaseError, drop_server_error::DropServerError,
+        remote_access_error::RemoteAccessError,
+    },
+};
+
+use super::{auth::generate_authorization_header, client::remote_client};
+
+/// Builds a signed GET request against the active server
 pub fn make_request<T: AsRef<str>, F: FnOnce(RequestBuilder) -> RequestBuilder>(
-    client: &Client,
     path_components: &[T],
     query: &[(T, T)],
     f: F,
 ) -> Result<RequestBuilder, RemoteAccessError> {
-    let mut base_url = DB.fetch_base_url();
+    let profile = borrow_db_checked()?.active_profile.clone();
+    make_profile_request(&profile, path_components, query, f)
+}
+
+/// Same as make_request, but against the server the game was fetched from
+pub fn make_game_request<T: AsRef<str>, F: FnOnce(RequestBuilder) -> RequestBuilder>(
+    game_id: &str,
+    path_components: &[T],
+    query: &[(T, T)],
+    f: F,
+) -> Result<RequestBuilder, RemoteAccessError> {
+    let profile = borrow_db_checked()?.game_profile(game_id).to_string();
+    make_profile_request(&profile, path_components, query, f)
+}
+
+/// The profile a game was fetched from, and the ID its server knows it
+/// by, which is what any request about the game has to use
+pub fn game_source(game_id: &str) -> Result<(String, String), DatabaseError> {
+    let db_handle = borrow_db_checked()?;
+    Ok((
+        db_handle.game_profile(game_id).to_string(),
+        db_handle.server_game_id(game_id).to_string(),
+    ))
+}
+
+/// Same as make_request, but against the server of the given profile
+pub fn make_profile_request<T: AsRef<str>, F: FnOnce(RequestBuilder) -> RequestBuilder>(
+    profile: &str,
+    path_components: &[T],
+    query: &[(T, T)],
+    f: F,
+) -> Result<RequestBuilder, RemoteAccessError> {
+    make_request_with_base(
+        profile,
+        profile_base_url(profile)?,
+        path_components,
+        query,
+        f,
+    )
+}
+
+/// Same as make_profile_request, but against an explicit base URL (e.g. a
+/// mirror) rather than the profile's server
+pub fn make_request_with_base<T: AsRef<str>, F: FnOnce(RequestBuilder) -> RequestBuilder>(
+    profile: &str,
+    base_url: Url,
+    path_components: &[T],
+    query: &[(T, T)],
+    f: F,
+) -> Result<RequestBuilder, RemoteAccessError> {
+    let url = build_url(base_url, path_components, query)?;
+    let response = remote_client(&url)?
+        .get(url)
+        .header("Authorization", generate_authorization_header(profile)?);
+    Ok(f(response))
+}
+
+pub fn profile_base_url(profile: &str) -> Result<Url, RemoteAccessError> {
+    let handle = borrow_db_checked()?;
+    let server = handle
+        .profiles
+        .get(profile)
+        .ok_or_else(|| RemoteAccessError::ProfileNotFound(profile.to_string()))?;
+    Ok(Url::parse(&server.base_url)?)
+}
+
+/// Builds a GET request for the async download pipeline, signed for the
+/// given profile if there is one. The client is passed in, as
+/// async_remote_client can't be called from async code
+pub fn make_async_request_with_base<T: AsRef<str>>(
+    client: &reqwest::Client,
+    profile: Option<&str>,
+    base_url: Url,
+    path_components: &[T],
+    query: &[(T, T)],
+) -> Result<reqwest::RequestBuilder, RemoteAccessError> {
+    let url = build_url(base_url, path_components, query)?;
+    let request = client.get(url);
+    Ok(match profile {
+        Some(profile) => request.header("Authorization", generate_authorization_header(profile)?),
+        None => request,
+    })
+}
+
+fn build_url<T: AsRef<str>>(
+    mut base_url: Url,
+    path_components: &[T],
+    query: &[(T, T)],
+) -> Result<Url, RemoteAccessError> {
     for endpoint in path_components {
         base_url = base_url.join(endpoint.as_ref())?;
     }
@@ -18,6 +121,95 @@ pub fn make_request<T: AsRef<str>, F: FnOnce(RequestBuilder) -> RequestBuilder>(
             queries.append_pair(param.as_ref(), val.as_ref());
         }
     }
-    let response = client.get(base_url);
-    Ok(f(response))
+    Ok(base_url)
+}
+
+/// Sends a request, turning anything other than a success status into an
+/// error. The server's error body is decoded where there is one
+pub fn send_request(request: RequestBuilder) -> Result<Response, RemoteAccessError> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let method = request.method().clone();
+    // Only the path is logged, as the query can be long
+    let path = request.url().path().to_string();
+
+    let start = Instant::now();
+    let response = client.execute(request).map_err(|e| {
+        warn!("{} {} failed: {}", method, path, e);
+        RemoteAccessError::from(e)
+    })?;
+    let status = response.status();
+    debug!(
+        "{} {} returned {} after {}ms",
+        method,
+        path,
+        status,
+        start.elapsed().as_millis()
+    );
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let url = response.url().to_string();
+    let body = response.text().unwrap_or_default();
+    Err(server_error(&method, &path, status, url, body))
+}
+
+/// Async version of send_request
+pub async fn send_async_request(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, RemoteAccessError> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let method = request.method().clone();
+    let path = request.url().path().to_string();
+
+    let start = Instant::now();
+    let response = client.execute(request).await.map_err(|e| {
+        warn!("{} {} failed: {}", method, path, e);
+        RemoteAccessError::from(e)
+    })?;
+    let status = response.status();
+    debug!(
+        "{} {} returned {} after {}ms",
+        method,
+        path,
+        status,
+        start.elapsed().as_millis()
+    );
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let url = response.url().to_string();
+    let body = response.text().await.unwrap_or_default();
+    Err(server_error(&method, &path, status, url, body))
+}
+
+fn server_error(
+    method: &Method,
+    path: &str,
+    status: StatusCode,
+    url: String,
+    body: String,
+) -> RemoteAccessError {
+    let error = decode_server_error(status, url, body);
+    warn!(
+        "{} {} returned {}: {}",
+        method, path, status, error.status_message
+    );
+    if error.status_message == "Nonce expired" {
+        return RemoteAccessError::OutOfSync;
+    }
+    RemoteAccessError::InvalidResponse(error)
+}
+
+// Proxies and older servers don't always answer with a JSON error
+fn decode_server_error(status: StatusCode, url: String, body: String) -> DropServerError {
+    serde_json::from_str(&body).unwrap_or_else(|_| DropServerError {
+        status_code: status.as_u16().into(),
+        status_message: status.canonical_reason().unwrap_or_default().to_string(),
+        message: body,
+        url,
+    })
 }
@@ -0,0 +1,308 @@
+use std::{
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+    thread::spawn,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    database::db::{
+        borrow_db_checked, set_game_status, AvailableUpdate, GameDownloadStatus, GameVersion,
+        UpdatePolicy,
+    },
+    download_manager::{
+        download_manager::DownloadManager, downloadable::Downloadable,
+        downloadable_metadata::DownloadableMetadata,
+    },
+    error::{library_error::LibraryError, remote_access_error::RemoteAccessError},
+    games::{
+        downloads::download_agent::GameDownloadAgent,
+        library::{can_modify_game, fetch_valid_game_versions, get_current_meta},
+    },
+    AppState, AppStatus,
+};
+
+const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Set by request_update_check, to wake the checker before the interval is up
+static CHECK_REQUESTED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+/// Checks every installed game for updates once at startup, then every
+/// UPDATE_CHECK_INTERVAL and whenever request_update_check is called
+pub fn spawn_update_checker(app_handle: AppHandle) {
+    spawn(move || loop {
+        check_for_updates(&app_handle);
+        wait_for_next_check();
+    });
+}
+
+/// Runs a check straight away, rather than at the next interval. Checks
+/// are skipped while signed out, so this is called on signing in and on
+/// switching server profile
+pub fn request_update_check() {
+    let (requested, wake) = &CHECK_REQUESTED;
+    *requested.lock().unwrap() = true;
+    wake.notify_one();
+}
+
+fn wait_for_next_check() {
+    let (requested, wake) = &CHECK_REQUESTED;
+    let (mut requested, _) = wake
+        .wait_timeout_while(
+            requested.lock().unwrap(),
+            UPDATE_CHECK_INTERVAL,
+            |requested| !*requested,
+        )
+        .unwrap();
+    *requested = false;
+}
+
+pub fn check_for_updates(app_handle: &AppHandle) {
+    let Ok(db_handle) = borrow_db_checked() else {
+        return;
+    };
+    let installed: Vec<DownloadableMetadata> = db_handle
+        .applications
+        .installed_game_version
+        .values()
+        .cloned()
+        .collect();
+    drop(db_handle);
+
+    debug!("checking {} installed games for updates", installed.len());
+    for meta in installed {
+        check_game_for_updates(app_handle, &meta);
+    }
+}
+
+/// Refreshes the available update for a single game, and queues
+/// it if the game is set to update automatically
+pub fn check_game_for_updates(app_handle: &AppHandle, meta: &DownloadableMetadata) {
+    let state = app_handle.state::<Mutex<AppState>>();
+    if !matches!(state.lock().unwrap().status, AppStatus::SignedIn) {
+        debug!("not signed in, skipping update check");
+        return;
+    }
+
+    let Ok(db_handle) = borrow_db_checked() else {
+        return;
+    };
+    let policy = db_handle
+        .applications
+        .update_policies
+        .get(&meta.id)
+        .cloned()
+        .unwrap_or_default();
+    let rolled_back_from = db_handle
+        .applications
+        .rolled_back_versions
+        .get(&meta.id)
+        .cloned();
+    drop(db_handle);
+
+    let update = match find_update(meta, &policy, &state) {
+        Ok(update) => update,
+        Err(e) => {
+            warn!("could not check {} for updates: {}", meta.id, e);
+            return;
+        }
+    };
+    // A version the user rolled back from is still offered, just never
+    // installed without them asking
+    let is_rolled_back = update
+        .as_ref()
+        .is_some_and(|update| Some(&update.version_name) == rolled_back_from.as_ref());
+    if is_rolled_back {
+        debug!(
+            "not automatically updating {}, as it was rolled back",
+            meta.id
+        );
+    }
+    let should_queue =
+        update.is_some() && !is_rolled_back && matches!(policy, UpdatePolicy::AutoUpdate {});
+    set_available_update(app_handle, meta, update);
+
+    if should_queue {
+        let download_manager = state.lock().unwrap().download_manager.clone();
+        if download_manager
+            .read_queue()
+            .iter()
+            .any(|queued| queued.id == meta.id)
+        {
+            return;
+        }
+        info!("automatically updating {}", meta.id);
+        if let Err(e) = queue_game_update(&meta.id, &download_manager) {
+            warn!("could not queue update for {}: {}", meta.id, e);
+        }
+    }
+}
+
+/// Returns the version the game should be moved to, if any. Pinned games
+/// only ever move to their pinned version, which may be older
+fn find_update(
+    meta: &DownloadableMetadata,
+    policy: &UpdatePolicy,
+    state: &Mutex<AppState>,
+) -> Result<Option<AvailableUpdate>, RemoteAccessError> {
+    let installed_version = match &meta.version {
+        Some(version) => version,
+        None => return Ok(None),
+    };
+
+    let versions = fetch_valid_game_versions(&meta.id, state)?;
+
+    if let UpdatePolicy::Pinned { version_name } = policy {
+        if version_name == installed_version {
+            return Ok(None);
+        }
+        if !versions.iter().any(|v| v.version_name == *version_name) {
+            warn!(
+                "{} is pinned to {}, which the server no longer has",
+                meta.id, version_name
+            );
+            return Ok(None);
+        }
+        return Ok(Some(AvailableUpdate {
+            version_name: version_name.clone(),
+        }));
+    }
+
+    Ok(
+        find_newer_version(meta, installed_version, &versions).map(|newest| AvailableUpdate {
+            version_name: newest.version_name.clone(),
+        }),
+    )
+}
+
+fn find_newer_version<'a>(
+    meta: &DownloadableMetadata,
+    installed_version: &String,
+    versions: &'a [GameVersion],
+) -> Option<&'a GameVersion> {
+    let newest = versions.iter().max_by_key(|v| v.version_index)?;
+
+    // Prefer what the server says about the installed version, as
+    // versions can be reordered after they've been published
+    let installed_index = versions
+        .iter()
+        .find(|v| v.version_name == *installed_version)
+        .map(|v| v.version_index)
+        .or_else(|| {
+            borrow_db_checked()
+                .ok()?
+                .applications
+                .game_versions
+                .get(&meta.id)
+                .and_then(|versions| versions.get(installed_version))
+                .map(|v| v.version_index)
+        });
+
+    let is_newer = match installed_index {
+        Some(installed_index) => newest.version_index > installed_index,
+        // The installed version is gone from the server, so anything is an update
+        None => newest.version_name != *installed_version,
+    };
+
+    is_newer.then_some(newest)
+}
+
+fn set_available_update(
+    app_handle: &AppHandle,
+    meta: &DownloadableMetadata,
+    update: Option<AvailableUpdate>,
+) {
+    let Ok(db_handle) = borrow_db_checked() else {
+        return;
+    };
+    let current = db_handle
+        .applications
+        .available_updates
+        .get(&meta.id)
+        .cloned();
+    drop(db_handle);
+    if current == update {
+        return;
+    }
+
+    if let Some(update) = &update {
+        info!("{} can be updated to {}", meta.id, update.version_name);
+    }
+    let saved = set_game_status(app_handle, meta.clone(), |db_handle, meta| match update {
+        Some(update) => {
+            db_handle
+                .applications
+                .available_updates
+                .insert(meta.id.clone(), update);
+        }
+        None => {
+            db_handle.applications.available_updates.remove(&meta.id);
+        }
+    });
+    if let Err(e) = saved {
+        warn!("could not save available update for {}: {}", meta.id, e);
+    }
+}
+
+/// Queues the available update for an installed game into the directory
+/// it is already installed in, so it is updated in place
+pub fn queue_game_update(
+    game_id: &String,
+    download_manager: &DownloadManager,
+) -> Result<(), LibraryError> {
+    let meta = match get_current_meta(game_id)? {
+        Some(data) => data,
+        None => return Err(LibraryError::MetaNotFound(game_id.clone())),
+    };
+    can_modify_game(&meta)?;
+
+    let db_lock = borrow_db_checked()?;
+    let update = db_lock
+        .applications
+        .available_updates
+        .get(game_id)
+        .cloned()
+        .ok_or_else(|| LibraryError::NoUpdateAvailable(game_id.clone()))?;
+    // The available update should already respect the pin, but
+    // a stale one from before the game was pinned might not
+    if let Some(UpdatePolicy::Pinned { version_name }) =
+        db_lock.applications.update_policies.get(game_id)
+    {
+        if *version_name != update.version_name {
+            return Err(LibraryError::VersionPinned(
+                game_id.clone(),
+                version_name.clone(),
+            ));
+        }
+    }
+    let install_dir = match db_lock.applications.game_statuses.get(game_id) {
+        Some(GameDownloadStatus::Installed { install_dir, .. })
+        | Some(GameDownloadStatus::SetupRequired { install_dir, .. })
+        | Some(GameDownloadStatus::PartiallyInstalled { install_dir, .. }) => install_dir.clone(),
+        _ => return Err(LibraryError::MetaNotFound(game_id.clone())),
+    };
+    // Games are always installed to <install dir>/<game id>
+    let base_dir = Path::new(&install_dir).parent().unwrap_or(Path::new(""));
+    let install_dir_index = db_lock
+        .applications
+        .install_dirs
+        .iter()
+        .position(|dir| dir == base_dir)
+        .ok_or_else(|| LibraryError::InstallDirNotConfigured(base_dir.to_path_buf()))?;
+    drop(db_lock);
+
+    info!("queueing update of {} to {}", game_id, update.version_name);
+    let game_download_agent = Arc::new(Box::new(GameDownloadAgent::new(
+        game_id.clone(),
+        update.version_name,
+        install_dir_index,
+        false,
+        download_manager.get_sender(),
+    )?) as Box<dyn Downloadable + Send + Sync>);
+    download_manager
+        .queue_download(game_download_agent)
+        .map_err(|_| LibraryError::QueueFailed(game_id.clone()))
+}
@@ -34,6 +34,11 @@ export type Game = {
   mBannerId: string;
   mCoverId: string;
   mImageLibrary: string[];
+  // Name of the server profile the game comes from
+  profile: string;
+  // What that server calls the game, which only differs from id when
+  // another profile has a game with the same ID
+  serverId: string;
 };
 
 export enum AppStatus {
@@ -42,6 +47,8 @@ export enum AppStatus {
   SignedIn = "SignedIn",
   SignedInNeedsReauth = "SignedInNeedsReauth",
   ServerUnavailable = "ServerUnavailable",
+  CredentialsLocked = "CredentialsLocked",
+  CredentialStoreUnavailable = "CredentialStoreUnavailable",
 }
 
 export enum GameStatusEnum {
@@ -49,8 +56,11 @@ export enum GameStatusEnum {
   Queued = "Queued",
   Downloading = "Downloading",
   Installed = "Installed",
+  PartiallyInstalled = "PartiallyInstalled",
   Updating = "Updating",
   Uninstalling = "Uninstalling",
+  Moving = "Moving",
+  RollingBack = "RollingBack",
   SetupRequired = "SetupRequired",
   Running = "Running"
 }
@@ -58,6 +68,7 @@ export enum GameStatusEnum {
 export type GameStatus = {
   type: GameStatusEnum;
   version_name?: string;
+  updateAvailable?: string;
 };
 
 export enum DownloadableType {
@@ -76,4 +87,90 @@ export type DownloadableMetadata = {
 export type Settings = {
   autostart: boolean,
   maxDownloadThreads: number,
-}
\ No newline at end of file
+  adaptiveDownloadThreads: boolean,
+  keepPreviousVersion: boolean,
+  connectTimeout: number,
+  readTimeout: number,
+  serverTls: { [server: string]: ServerTlsSettings },
+  proxy: ProxySettings,
+  databaseBackups: number,
+  mergedLibrary: boolean,
+}
+
+export type ServerProfile = {
+  name: string,
+  baseUrl: string,
+  signedIn: boolean,
+  active: boolean,
+}
+
+export type ProxySettings =
+  | { type: "System" }
+  | { type: "Direct" }
+  | {
+      type: "Manual",
+      http?: string,
+      https?: string,
+      socks5?: string,
+      noProxy: string,
+      credentials?: { username: string, password: string },
+    };
+
+export type ServerTlsSettings = {
+  caBundle?: string,
+  pinnedFingerprint?: string,
+}
+
+export type GameDiskUsage = {
+  gameId: string,
+  size: number,
+}
+
+export type DownloadDirStats = {
+  path: string,
+  totalSpace: number,
+  freeSpace: number,
+  usedByGames: number,
+  games: GameDiskUsage[],
+  filesystem?: string,
+  depot?: string,
+}
+
+export type CredentialStatus = {
+  protection: "None" | "Keyring" | "Passphrase",
+  locked: boolean,
+  keyringAvailable: boolean,
+}
+
+export type Inconsistency = {
+  gameId: string,
+  version?: string,
+  kind:
+    | "MissingInstallDir"
+    | "MissingVersionData"
+    | "IncompleteInstall"
+    | "InstalledVersionMismatch"
+    | "LeftoverTransientStatus",
+  fixed: boolean,
+}
+
+export type OrphanedData = {
+  path: string,
+  kind: "Untracked" | "PartialInstall" | "StaleManifest",
+  size: number,
+  installDir: number,
+  gameId?: string,
+  version?: string,
+}
+
+export type UpdatePolicy =
+  | { type: "AutoUpdate" }
+  | { type: "NotifyOnly" }
+  | { type: "Pinned"; version_name: string };
+
+export type InstalledVersion = {
+  versionName: string,
+  installDir: string,
+  setupRequired: boolean,
+  default: boolean,
+}